@@ -0,0 +1,43 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use largo::{default_env, eval_program};
+
+// many distinct names bound and looked up through nested scopes
+const SYMBOLS: &str = "
+(define add3 (lambda (first second third) (+ first (+ second third))))
+(define walk
+  (lambda (count total)
+    (if (= count 0)
+        total
+        (let ((left count) (right (- count 1)))
+          (walk right (add3 total left right))))))
+(walk 2000 0)
+";
+
+// builtin calls nested a few levels deep, applied many times over
+const ARITHMETIC: &str = "
+(define poly
+  (lambda (x)
+    (+ (* 3 (* x (* x x))) (- (* 2 (* x x)) (/ (+ x 1) 2)) (min (abs (- x 7)) (max x 4)))))
+(define sum
+  (lambda (n acc)
+    (if (= n 0)
+        acc
+        (sum (- n 1) (+ acc (poly n))))))
+(sum 2000 0)
+";
+
+fn symbols(c: &mut Criterion) {
+    c.bench_function("symbol lookups", |b| {
+        b.iter(|| eval_program(SYMBOLS, &mut default_env()).unwrap())
+    });
+}
+
+fn arithmetic(c: &mut Criterion) {
+    c.bench_function("nested arithmetic", |b| {
+        b.iter(|| eval_program(ARITHMETIC, &mut default_env()).unwrap())
+    });
+}
+
+criterion_group!(benches, symbols, arithmetic);
+criterion_main!(benches);
@@ -1,291 +1,6414 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use num_bigint::BigInt;
+use num_traits::{FromPrimitive, Signed, ToPrimitive, Zero};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use thiserror::Error;
 
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
 use std::collections::HashMap;
-use std::io::Write;
-use std::{fmt, io};
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::iter::Peekable;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::str::Chars;
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::{LazyLock, Mutex};
+use std::thread;
+use std::time::Instant;
 
+/// Errors raised while tokenizing, parsing or evaluating
+///
+/// `Reason` covers whatever has no more specific variant
 #[derive(Error, Debug)]
-enum Error {
+pub enum Error {
     #[error("{0}")]
     Reason(String),
-    // #[error("Syntax error; line:{0} col:{1}")]
-    // SyntaxErr(u32, u32),
-    // #[error("Parens not balanced; {0} parens needed")]
-    // UnbalancedParens(usize),
+    /// Source that can't be tokenized or parsed; `span` is the byte range of
+    /// the offending text, and `line` and `col` where it starts
+    #[error("Syntax error; line:{line} col:{col}; {message}")]
+    ParseError {
+        message: String,
+        span: Range<usize>,
+        line: u32,
+        col: u32,
+    },
+    #[error("Parens not balanced; {0} parens needed to close `(` at line:{1} col:{2}")]
+    UnbalancedParens(usize, u32, u32),
+    #[error("Unbound symbol `{0}`")]
+    UnboundSymbol(String),
+    /// A call whose operator isn't a function; holds the operator as written
+    #[error("`{0}` is not a function")]
+    NotCallable(String),
+    /// `max` is `None` when any number of extra arguments is allowed
+    #[error("{callee} expected {}, got {got}", describe_arity(*.min, *.max))]
+    ArityMismatch {
+        callee: String,
+        min: usize,
+        max: Option<usize>,
+        got: usize,
+    },
+    /// Both sides are type names, as given by `type-of`
+    #[error("Expected {expected}, got {got}")]
+    TypeError { expected: String, got: String },
+    /// Raised by a program itself with `error`, carrying its message
+    #[error("{0}")]
+    UserError(String),
 }
 
-#[derive(Clone, Debug, PartialEq)]
-enum Expr {
+// e.g. "1 argument", "at least 2 arguments" or "1 to 3 arguments"
+fn describe_arity(min: usize, max: Option<usize>) -> String {
+    let noun = |n: usize| if n == 1 { "argument" } else { "arguments" };
+    match max {
+        None => format!("at least {min} {}", noun(min)),
+        Some(max) if max == min => format!("{min} {}", noun(min)),
+        Some(max) => format!("{min} to {max} {}", noun(max)),
+    }
+}
+
+/// A Largo value, which is also the syntax tree produced by `parse`
+///
+/// Lists and lambdas share their contents, so cloning one (as every symbol
+/// lookup does) only bumps a reference count
+///
+/// With the `serde` feature every variant but the functions can be serialized;
+/// trying to serialize a function is an error
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Expr {
     Bool(bool),
-    Symbol(String),
+    Symbol(Symbol),
+    Int(i64),
+    /// An integer too big for `Int`; smaller results always come back as one
+    BigInt(Rc<BigInt>),
+    /// An exact fraction such as `3/4`, always in lowest terms with a
+    /// denominator above 1 (anything else is an `Int`)
+    Ratio {
+        num: i64,
+        den: i64,
+    },
     Number(f64),
-    List(Vec<Expr>),
+    String(String),
+    Char(char),
+    List(Rc<Vec<Expr>>),
+    Vector(Rc<Vec<Expr>>),
+    /// Several results at once, made by `values` and taken apart by
+    /// `let-values`
+    Values(Rc<Vec<Expr>>),
+    #[cfg_attr(feature = "serde", serde(with = "dict_entries"))]
+    Dict(Rc<HashMap<DictKey, Expr>>),
+    #[cfg_attr(feature = "serde", serde(skip))]
     Func(fn(&[Expr]) -> Result<Expr>),
+    /// A builtin that also gets the scope it was called from
+    #[cfg_attr(feature = "serde", serde(skip))]
+    EnvFunc(fn(&[Expr], &mut Env) -> Result<Expr>),
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Lambda {
+        params: Rc<Params>,
+        body: Rc<Expr>,
+        env: Rc<Env>,
+    },
+    /// Like a lambda, but called with its arguments unevaluated; what it
+    /// returns is evaluated in their place
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Macro {
+        params: Rc<Params>,
+        body: Rc<Expr>,
+        env: Rc<Env>,
+    },
+    /// The result of `delay`, shared so forcing any copy forces them all
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Promise(Rc<RefCell<Promise>>),
+}
+
+/// A `delay`ed expression, evaluated at most once by `force`
+#[derive(Debug)]
+pub enum Promise {
+    Delayed(Rc<Expr>, Env),
+    Forced(Expr),
+}
+
+// structural equality; functions are never equal, not even to themselves,
+// since neither fn pointers nor closures can be compared meaningfully, and
+// promises are only equal to themselves
+impl PartialEq for Expr {
+    fn eq(&self, other: &Expr) -> bool {
+        match (self, other) {
+            (Expr::Bool(a), Expr::Bool(b)) => a == b,
+            (Expr::Symbol(a), Expr::Symbol(b)) => a == b,
+            (Expr::Int(a), Expr::Int(b)) => a == b,
+            (Expr::BigInt(a), Expr::BigInt(b)) => a == b,
+            (Expr::Ratio { num: a, den: c }, Expr::Ratio { num: b, den: d }) => (a, c) == (b, d),
+            (Expr::Number(a), Expr::Number(b)) => a == b,
+            (Expr::String(a), Expr::String(b)) => a == b,
+            (Expr::Char(a), Expr::Char(b)) => a == b,
+            // comparing nested lists nests as deeply as they do
+            (Expr::List(a), Expr::List(b)) => with_stack(|| a == b),
+            (Expr::Vector(a), Expr::Vector(b)) => with_stack(|| a == b),
+            (Expr::Values(a), Expr::Values(b)) => with_stack(|| a == b),
+            (Expr::Dict(a), Expr::Dict(b)) => a == b,
+            (Expr::Promise(a), Expr::Promise(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl Expr {
+    /// The empty list, which also stands for "nothing"; like every value
+    /// other than `false` it counts as true
+    pub fn nil() -> Expr {
+        Expr::List(Rc::new(vec![]))
+    }
+
+    /// The name `type-of` gives this value's type: one of `bool`, `symbol`,
+    /// `number` (ints, ratios and floats alike), `string`, `char`, `list`,
+    /// `vector`, `values`, `dict`, `function` (builtins and lambdas), `macro`
+    /// or `promise`
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Expr::Bool(_) => "bool",
+            Expr::Symbol(_) => "symbol",
+            Expr::Int(_) | Expr::BigInt(_) | Expr::Ratio { .. } | Expr::Number(_) => "number",
+            Expr::String(_) => "string",
+            Expr::Char(_) => "char",
+            Expr::List(_) => "list",
+            Expr::Vector(_) => "vector",
+            Expr::Values(_) => "values",
+            Expr::Dict(_) => "dict",
+            Expr::Func(_) | Expr::EnvFunc(_) | Expr::Lambda { .. } => "function",
+            Expr::Macro { .. } => "macro",
+            Expr::Promise(_) => "promise",
+        }
+    }
 }
 
 impl fmt::Display for Expr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let repr = match self {
-            Expr::Bool(b) => b.to_string(),
-            Expr::Symbol(s) => s.clone(),
-            Expr::Number(n) => n.to_string(),
-            Expr::List(l) => {
-                let l: Vec<String> = l.iter().map(|expr| expr.to_string()).collect();
-                format!("({})", l.join(","))
-            }
-            Expr::Func(_) => "Function".to_owned(),
+        with_stack(|| {
+            let repr = match self {
+                Expr::Bool(b) => b.to_string(),
+                Expr::Symbol(s) => s.to_string(),
+                Expr::Int(n) => n.to_string(),
+                Expr::BigInt(n) => n.to_string(),
+                Expr::Ratio { num, den } => format!("{num}/{den}"),
+                // floats keep their `.0` so they can't be mistaken for ints
+                Expr::Number(n) => format!("{n:?}"),
+                Expr::String(s) => format!("\"{}\"", escape_string(s)),
+                Expr::Char(c) => match CHAR_NAMES.iter().find(|(_, named)| named == c) {
+                    Some((name, _)) => format!("#\\{name}"),
+                    None => format!("#\\{c}"),
+                },
+                Expr::List(l) => {
+                    let l: Vec<String> = l.iter().map(|expr| expr.to_string()).collect();
+                    format!("({})", l.join(" "))
+                }
+                Expr::Vector(v) => {
+                    let v: Vec<String> = v.iter().map(|expr| expr.to_string()).collect();
+                    format!("#({})", v.join(" "))
+                }
+                // as the REPL shows them: one after another
+                Expr::Values(v) => {
+                    let v: Vec<String> = v.iter().map(|expr| expr.to_string()).collect();
+                    v.join(" ")
+                }
+                // sorted, since the map itself has no order
+                Expr::Dict(d) => {
+                    let mut entries: Vec<String> = d
+                        .iter()
+                        .map(|(key, value)| format!("{key} {value}"))
+                        .collect();
+                    entries.sort();
+                    format!("{{{}}}", entries.join(" "))
+                }
+                Expr::Func(_) | Expr::EnvFunc(_) => "Function".to_owned(),
+                Expr::Lambda { .. } => "Lambda".to_owned(),
+                Expr::Macro { .. } => "Macro".to_owned(),
+                Expr::Promise(_) => "Promise".to_owned(),
+            };
+            write!(f, "{}", repr)
+        })
+    }
+}
+
+// dropping a deeply nested list would otherwise recurse once per level, so the
+// lists only this value holds are taken apart one at a time instead
+impl Drop for Expr {
+    fn drop(&mut self) {
+        let (Expr::List(items) | Expr::Vector(items) | Expr::Values(items)) = self else {
+            return;
+        };
+        let Some(items) = Rc::get_mut(items) else {
+            return;
         };
-        write!(f, "{}", repr)
+        let mut pending = std::mem::take(items);
+        while let Some(mut expr) = pending.pop() {
+            if let Expr::List(items) | Expr::Vector(items) | Expr::Values(items) = &mut expr {
+                if let Some(items) = Rc::get_mut(items) {
+                    pending.append(items);
+                }
+            }
+        }
     }
 }
 
-#[derive(Clone, Debug)]
-struct Env {
-    data: HashMap<String, Expr>,
+/// The parameter list of a lambda or macro: `(a b . rest)` takes two or more
+/// arguments, binding `rest` to a list of any past the second, and
+/// `(a (b 10))` takes one or two, with `b` defaulting to `10`
+#[derive(Debug)]
+pub struct Params {
+    required: Vec<Symbol>,
+    optional: Vec<(Symbol, Expr)>,
+    rest: Option<Symbol>,
 }
 
-fn tokenize(expr: String) -> Vec<String> {
-    expr.replace('(', " ( ")
-        .replace(')', " ) ")
-        .split_whitespace()
-        .map(|x| x.to_owned())
-        .collect()
+/// The values that can key an `Expr::Dict`
+///
+/// Floats and functions have no useful notion of equality, so they are left out
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum DictKey {
+    Bool(bool),
+    Symbol(Symbol),
+    Int(i64),
+    String(String),
+    Char(char),
 }
 
-fn parse(tokens: &[String]) -> Result<(Expr, &[String])> {
-    let (token, rest) = tokens
-        .split_first()
-        .ok_or(Error::Reason("Could not get token".to_owned()))?;
-    match token.as_str() {
-        "(" => read_seq(rest),
-        ")" => Err(Error::Reason("Unexpected `)`".to_owned()).into()),
-        _ => Ok((parse_atom(token), rest)),
+impl TryFrom<&Expr> for DictKey {
+    type Error = anyhow::Error;
+
+    fn try_from(expr: &Expr) -> Result<DictKey> {
+        match expr {
+            Expr::Bool(b) => Ok(DictKey::Bool(*b)),
+            Expr::Symbol(s) => Ok(DictKey::Symbol(*s)),
+            Expr::Int(n) => Ok(DictKey::Int(*n)),
+            Expr::String(s) => Ok(DictKey::String(s.clone())),
+            Expr::Char(c) => Ok(DictKey::Char(*c)),
+            _ => Err(Error::Reason(format!("`{expr}` cannot be a dict key")).into()),
+        }
     }
 }
 
-fn read_seq(tokens: &[String]) -> Result<(Expr, &[String])> {
-    let mut result: Vec<Expr> = vec![];
-    let mut xs = tokens;
-    loop {
-        let (next_token, rest) = xs
-            .split_first()
-            .ok_or(Error::Reason("Could not find closing `)`".to_owned()))?;
-        if next_token == ")" {
-            return Ok((Expr::List(result), rest));
+impl From<DictKey> for Expr {
+    fn from(key: DictKey) -> Expr {
+        match key {
+            DictKey::Bool(b) => Expr::Bool(b),
+            DictKey::Symbol(s) => Expr::Symbol(s),
+            DictKey::Int(n) => Expr::Int(n),
+            DictKey::String(s) => Expr::String(s),
+            DictKey::Char(c) => Expr::Char(c),
         }
-        let (expr, new_xs) = parse(xs)?;
-        result.push(expr);
-        xs = new_xs;
     }
 }
 
-fn parse_atom(token: &str) -> Expr {
-    match token {
-        "true" => Expr::Bool(true),
-        "false" => Expr::Bool(false),
-        _ => {
-            let parse_result = token.parse();
-            match parse_result {
-                Ok(v) => Expr::Number(v),
-                Err(_) => Expr::Symbol(token.to_owned()),
-            }
+// dicts are written as a list of `[key, value]` pairs, since formats like JSON
+// only allow strings as map keys
+#[cfg(feature = "serde")]
+mod dict_entries {
+    use super::{DictKey, Expr};
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    pub fn serialize<S: Serializer>(
+        dict: &Rc<HashMap<DictKey, Expr>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(dict.iter())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Rc<HashMap<DictKey, Expr>>, D::Error> {
+        let entries = Vec::<(DictKey, Expr)>::deserialize(deserializer)?;
+        Ok(Rc::new(entries.into_iter().collect()))
+    }
+}
+
+impl fmt::Display for DictKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", Expr::from(self.clone()))
+    }
+}
+
+/// An interned symbol name; equal names share one id, so comparing or
+/// hashing a symbol never touches its text
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+// every name interned so far; names are leaked so `Symbol::as_str` can hand
+// out `'static` references
+struct Interner {
+    ids: HashMap<&'static str, u32>,
+    names: Vec<&'static str>,
+}
+
+impl Interner {
+    fn intern(&mut self, name: &str) -> u32 {
+        if let Some(&id) = self.ids.get(name) {
+            return id;
         }
+        let name: &'static str = Box::leak(name.into());
+        let id = self.names.len() as u32;
+        self.names.push(name);
+        self.ids.insert(name, id);
+        id
     }
 }
 
-fn default_env() -> Env {
-    // `data` is a map from symbols to expressions
-    let mut data = HashMap::<String, Expr>::new();
-    data.insert(
-        "+".to_owned(),
-        Expr::Func(|args: &[Expr]| -> Result<Expr> {
-            let floats = parse_list_of_floats(args)?;
-            let sum: f64 = floats.iter().sum();
-            Ok(Expr::Number(sum))
-        }),
-    );
-    data.insert(
-        "-".to_owned(),
-        Expr::Func(|args: &[Expr]| -> Result<Expr> {
-            let floats = parse_list_of_floats(args)?;
-            let &first = floats.first().ok_or(Error::Reason(
-                "`-` requires at least one operand".to_owned(),
-            ))?;
-            let sum_rest: f64 = floats.iter().skip(1).sum();
-            Ok(Expr::Number(first - sum_rest))
-        }),
-    );
-    Env { data }
+// the special forms are interned first, so their ids index `SPECIAL_FORMS`
+static INTERNER: LazyLock<Mutex<Interner>> = LazyLock::new(|| {
+    let mut interner = Interner {
+        ids: HashMap::new(),
+        names: Vec::new(),
+    };
+    for name in SPECIAL_FORMS {
+        interner.intern(name);
+    }
+    Mutex::new(interner)
+});
+
+impl Symbol {
+    /// The symbol spelled `name`, interning it on first use
+    pub fn new(name: &str) -> Symbol {
+        Symbol(INTERNER.lock().unwrap().intern(name))
+    }
+
+    /// The text this symbol was interned from
+    pub fn as_str(self) -> &'static str {
+        INTERNER.lock().unwrap().names[self.0 as usize]
+    }
+
+    // the special form this symbol names, found without touching the table
+    fn special_form(self) -> Option<&'static str> {
+        SPECIAL_FORMS.get(self.0 as usize).copied()
+    }
 }
 
-fn parse_list_of_floats(floats: &[Expr]) -> Result<Vec<f64>> {
-    floats.iter().map(parse_single_float).collect()
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
-fn parse_single_float(expr: &Expr) -> Result<f64> {
-    match expr {
-        Expr::Number(num) => Ok(*num),
-        _ => Err(Error::Reason("Expected a number".to_owned()))?,
+// symbols are written as their names, since ids differ from run to run
+#[cfg(feature = "serde")]
+impl Serialize for Symbol {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
     }
 }
 
-fn eval(expr: &Expr, env: &mut Env) -> Result<Expr> {
-    match expr {
-        Expr::Bool(_) => Ok(expr.clone()),
-
-        // lookup symbol
-        Expr::Symbol(symbol) => Ok(env
-            .data
-            .get(symbol)
-            .ok_or(Error::Reason(format!("Unexpected symbol `{symbol}`")))
-            .cloned()?),
-
-        // return the number
-        Expr::Number(_) => Ok(expr.clone()),
-
-        // evaluate each item in list and apply
-        Expr::List(list) => {
-            // get car and cdr
-            let (op, args) = list
-                .split_first()
-                .ok_or(Error::Reason("Expected non-empty list".to_owned()))?;
-
-            // evaluate the operator
-            let op = eval(op, env)?;
-
-            // check that op is a function
-            match op {
-                Expr::Func(op) => {
-                    // evaluate args
-                    let args = args
-                        .iter()
-                        .map(|x| eval(x, env))
-                        .collect::<Result<Vec<Expr>>>()?;
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Symbol {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Symbol, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Ok(Symbol::new(&name))
+    }
+}
 
-                    // apply
-                    op(&args)
-                }
-                _ => Err(Error::Reason("Operator must be a function".to_owned()).into()),
-            }
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Symbol({:?})", self.as_str())
+    }
+}
+
+/// The bindings visible to an expression, innermost scope first
+///
+/// Cloning an `Env` shares its scopes rather than copying them, so closures
+/// see bindings made after they were created (which is what recursion needs)
+#[derive(Clone)]
+pub struct Env {
+    data: Rc<RefCell<HashMap<Symbol, Expr>>>,
+    parent: Option<Rc<Env>>,
+    depth: Rc<Depth>,
+    // where `eval` reports each step while tracing, shared like `depth`
+    trace: Rc<RefCell<Option<Box<dyn Write>>>>,
+    // where `print` and friends write, shared like `depth`
+    output: Rc<RefCell<Box<dyn Write>>>,
+}
+
+/// How deeply `eval` may nest before giving up, unless changed with
+/// `Env::set_max_depth`
+pub const DEFAULT_MAX_DEPTH: usize = 10_000;
+
+// nesting of `eval` calls, shared by every scope under the same root
+#[derive(Debug)]
+struct Depth {
+    current: Cell<usize>,
+    max: Cell<usize>,
+}
+
+// native stack that evaluation may use on a thread it didn't start itself;
+// threads get 2 MiB by default, and this leaves room for what the caller has
+// already used
+const STACK_BUDGET: usize = 1024 * 1024;
+
+// the stack size of each thread `with_stack` moves onto
+const STACK_SEGMENT: usize = 16 * 1024 * 1024;
+
+thread_local! {
+    // the highest stack address `with_stack` has seen on this thread, and how
+    // far below it may be used; a budget of 0 means `STACK_BUDGET`
+    static STACK_EXTENT: Cell<(usize, usize)> = const { Cell::new((0, 0)) };
+}
+
+// a value handed to a thread `with_stack` starts
+struct AssertSend<T>(T);
+
+// SAFETY: the thread that starts another in `with_stack` waits for it to
+// finish before going on, so the values handed over are only ever used by one
+// thread at a time, and no thread-locals are shared through them
+unsafe impl<T> Send for AssertSend<T> {}
+
+impl<T> AssertSend<T> {
+    fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+// run `f` here while this thread has native stack to spare, and otherwise on
+// a new thread with a stack of its own; everything that recurses over nested
+// code or data goes through this, so nesting is bounded by the depth limit
+// and memory rather than by the stack of whichever thread called in
+fn with_stack<T>(f: impl FnOnce() -> T) -> T {
+    let marker = 0u8;
+    let here = std::hint::black_box(&marker) as *const u8 as usize;
+    let (base, budget) = STACK_EXTENT.get();
+    // the stack grows down, so the outermost call has the highest address
+    let base = base.max(here);
+    let budget = if budget == 0 { STACK_BUDGET } else { budget };
+    STACK_EXTENT.set((base, budget));
+    if base - here < budget {
+        return f();
+    }
+    let f = AssertSend(f);
+    thread::scope(|scope| {
+        let deeper = thread::Builder::new()
+            .stack_size(STACK_SEGMENT)
+            .spawn_scoped(scope, move || {
+                let f = f.into_inner();
+                STACK_EXTENT.set((0, STACK_SEGMENT - STACK_BUDGET));
+                AssertSend(f())
+            })
+            .expect("could not start a thread to evaluate deeper on");
+        match deeper.join() {
+            Ok(result) => result.into_inner(),
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    })
+}
+
+// holds one level of `Depth` until dropped
+struct DepthGuard(Rc<Depth>);
+
+impl DepthGuard {
+    fn enter(depth: &Rc<Depth>) -> Result<DepthGuard> {
+        let current = depth.current.get();
+        if current >= depth.max.get() {
+            return Err(Error::Reason("maximum recursion depth exceeded".to_owned()).into());
         }
+        depth.current.set(current + 1);
+        Ok(DepthGuard(depth.clone()))
+    }
+}
 
-        // shouldn't be allowed
-        Expr::Func(_) => Err(Error::Reason("Cannot evaluate a function".to_owned()).into()),
+impl Drop for DepthGuard {
+    fn drop(&mut self) {
+        self.0.current.set(self.0.current.get() - 1);
     }
 }
 
-fn string_to_exp(lexemes: String, env: &mut Env) -> Result<Expr> {
-    let (parsed, _) = parse(&tokenize(lexemes))?;
-    let expr = eval(&parsed, env)?;
-    Ok(expr)
+impl Env {
+    fn new(data: HashMap<Symbol, Expr>, parent: Option<Rc<Env>>) -> Env {
+        let (depth, trace, output) = match &parent {
+            Some(parent) => (
+                parent.depth.clone(),
+                parent.trace.clone(),
+                parent.output.clone(),
+            ),
+            None => (
+                Rc::new(Depth {
+                    current: Cell::new(0),
+                    max: Cell::new(DEFAULT_MAX_DEPTH),
+                }),
+                Rc::new(RefCell::new(None)),
+                Rc::new(RefCell::new(Box::new(io::stdout()) as Box<dyn Write>)),
+            ),
+        };
+        Env {
+            data: Rc::new(RefCell::new(data)),
+            parent,
+            depth,
+            trace,
+            output,
+        }
+    }
+
+    /// Limit how deeply evaluation may nest in this environment and every
+    /// scope created from it
+    pub fn set_max_depth(&self, max: usize) {
+        self.depth.max.set(max);
+    }
+
+    /// Write each list `eval` evaluates in this environment (or any scope
+    /// created from it) to `out`, followed by the value it produced, indented
+    /// by how deeply evaluation is nested; `None` turns tracing off
+    pub fn set_trace(&self, out: Option<Box<dyn Write>>) {
+        *self.trace.borrow_mut() = out;
+    }
+
+    /// Send everything this environment (or any scope created from it)
+    /// prints to `out` instead of stdout
+    pub fn set_output(&self, out: Box<dyn Write>) {
+        *self.output.borrow_mut() = out;
+    }
+
+    // write `text` where this environment's output goes
+    fn write_output(&self, text: &str) -> io::Result<()> {
+        let mut out = self.output.borrow_mut();
+        out.write_all(text.as_bytes())?;
+        out.flush()
+    }
+
+    // a trace line at the current nesting depth; tracing is best-effort, so a
+    // failed write doesn't fail the evaluation
+    fn trace_line(&self, line: fmt::Arguments) {
+        if let Some(out) = self.trace.borrow_mut().as_mut() {
+            let indent = 2 * self.depth.current.get().saturating_sub(1);
+            let _ = writeln!(out, "{:indent$}{line}", "");
+        }
+    }
+
+    // a new scope on top of `parent`
+    fn with_parent(data: HashMap<Symbol, Expr>, parent: Rc<Env>) -> Env {
+        Env::new(data, Some(parent))
+    }
+
+    // look up `symbol` in this scope, then in each enclosing scope
+    fn get(&self, symbol: Symbol) -> Option<Expr> {
+        match self.data.borrow().get(&symbol) {
+            Some(expr) => Some(expr.clone()),
+            None => self.parent.as_ref()?.get(symbol),
+        }
+    }
+
+    // bind `symbol` in this scope, replacing any existing binding
+    fn insert(&self, symbol: Symbol, expr: Expr) {
+        self.data.borrow_mut().insert(symbol, expr);
+    }
+
+    // rebind `symbol` in the innermost scope that already binds it, handing
+    // `expr` back if no scope does
+    fn set(&self, symbol: Symbol, expr: Expr) -> Result<(), Expr> {
+        if let Some(slot) = self.data.borrow_mut().get_mut(&symbol) {
+            *slot = expr;
+            return Ok(());
+        }
+        match &self.parent {
+            Some(parent) => parent.set(symbol, expr),
+            None => Err(expr),
+        }
+    }
 }
 
-fn get_line() -> String {
-    let mut lexemes = String::new();
-    io::stdin()
-        .read_line(&mut lexemes)
-        .expect("Could not read line");
-    lexemes.trim().to_owned()
+// scopes compare by identity; comparing contents could recurse forever
+// through a closure that captured its own scope
+impl PartialEq for Env {
+    fn eq(&self, other: &Env) -> bool {
+        Rc::ptr_eq(&self.data, &other.data) && self.parent == other.parent
+    }
 }
 
-pub fn run_repl() -> Result<()> {
-    println!("~~~~ Largo ~~~~");
-    let mut env = default_env();
-    loop {
-        print!(">>> ");
-        io::stdout().flush()?;
-        let line = get_line();
-        if line == "quit" {
-            break;
+// only the names are printed, for the same reason
+impl fmt::Debug for Env {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut names: Vec<&str> = self.data.borrow().keys().map(|s| s.as_str()).collect();
+        names.sort();
+        f.debug_struct("Env")
+            .field("names", &names)
+            .field("parent", &self.parent)
+            .finish()
+    }
+}
+
+/// A token together with the line and column it starts at, and the byte
+/// range of the source it was read from
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token {
+    pub text: String,
+    pub line: u32,
+    pub col: u32,
+    pub span: Range<usize>,
+}
+
+impl Token {
+    // a parse error pointing at this token
+    fn error(&self, message: String) -> anyhow::Error {
+        Error::ParseError {
+            message,
+            span: self.span.clone(),
+            line: self.line,
+            col: self.col,
         }
-        let expr = string_to_exp(line, &mut env)?;
-        println!("{}", expr);
+        .into()
     }
-    Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+// walks the source one char at a time, keeping track of the position
+struct Scanner<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: u32,
+    col: u32,
+    // in bytes
+    offset: usize,
+}
 
-    #[test]
-    fn check_error() {
-        assert_eq!(
-            "Hello there".to_owned(),
-            format!("{}", Error::Reason("Hello there".to_owned()))
-        );
+impl<'a> Scanner<'a> {
+    fn new(source: &'a str) -> Scanner<'a> {
+        Scanner {
+            chars: source.chars().peekable(),
+            line: 1,
+            col: 1,
+            offset: 0,
+        }
     }
 
-    #[test]
-    fn check_tokenize() {
-        assert_eq!(
-            tokenize("(+ 1 2)".to_owned()),
-            vec![
-                "(".to_owned(),
-                "+".to_owned(),
-                "1".to_owned(),
-                "2".to_owned(),
-                ")".to_owned()
-            ]
-        );
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
     }
 
-    #[test]
-    fn check_parse() {
-        let lexemes = "(+ 1 2)".to_owned();
-        let tokens = tokenize(lexemes);
-        let (expr, rest) = parse(tokens.as_slice()).unwrap();
-        assert_eq!(
-            expr,
-            Expr::List(vec![
-                Expr::Symbol("+".to_owned()),
-                Expr::Number(1.0),
-                Expr::Number(2.0),
-            ])
-        );
-        assert!(rest.is_empty());
+    // the char after the next one
+    fn peek_second(&self) -> Option<char> {
+        self.chars.clone().nth(1)
     }
 
-    #[test]
-    fn check_parse_atom() {
-        assert_eq!(parse_atom("1.0"), Expr::Number(1.0));
-        assert_eq!(parse_atom("Hello"), Expr::Symbol("Hello".to_owned()));
-        assert_eq!(parse_atom("hi1.0hi"), Expr::Symbol("hi1.0hi".to_owned()));
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        self.offset += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
+        Some(c)
     }
 
-    #[test]
-    fn check_default_env() {
-        let Env { data } = default_env();
+    // a parse error covering everything from `start` up to here
+    fn error_since(&self, message: &str, start: usize, line: u32, col: u32) -> anyhow::Error {
+        Error::ParseError {
+            message: message.to_owned(),
+            span: start..self.offset,
+            line,
+            col,
+        }
+        .into()
+    }
 
-        let add = *match data.get("+").unwrap() {
-            Expr::Func(f) => f,
-            _ => panic!("data did not return addition"),
-        };
-        let sub = *match data.get("-").unwrap() {
-            Expr::Func(f) => f,
-            _ => panic!("data did not return subtraction"),
+    // consume a `"`-delimited literal, keeping the quotes and escapes
+    fn string_literal(&mut self) -> Result<String> {
+        let (start, line, col) = (self.offset, self.line, self.col);
+        let mut literal = String::new();
+        literal.extend(self.bump());
+        let mut escaped = false;
+        while let Some(c) = self.bump() {
+            literal.push(c);
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => return Ok(literal),
+                _ => {}
+            }
+        }
+        Err(self.error_since("Unterminated string literal", start, line, col))
+    }
+
+    // skip a `#|`...`|#` comment, which may contain others of its own
+    fn block_comment(&mut self) -> Result<()> {
+        let (start, line, col) = (self.offset, self.line, self.col);
+        let mut depth = 0;
+        while let Some(c) = self.bump() {
+            match (c, self.peek()) {
+                ('#', Some('|')) => depth += 1,
+                ('|', Some('#')) => depth -= 1,
+                _ => continue,
+            }
+            self.bump();
+            if depth == 0 {
+                return Ok(());
+            }
+        }
+        Err(self.error_since("Unterminated block comment", start, line, col))
+    }
+
+    // consume a `#\` char literal; the first char after `#\` is taken even if
+    // it would end an atom, so `#\(` and `#\ ` work, and any name follows it
+    fn char_literal(&mut self) -> String {
+        let mut literal = String::new();
+        literal.extend(self.bump());
+        literal.extend(self.bump());
+        literal.extend(self.bump());
+        literal.push_str(&self.atom());
+        literal
+    }
+
+    // consume characters up to the next delimiter
+    fn atom(&mut self) -> String {
+        let mut atom = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || "(){}'`,\";".contains(c) {
+                break;
+            }
+            atom.extend(self.bump());
+        }
+        atom
+    }
+}
+
+/// Split source text into tokens
+pub fn tokenize(expr: String) -> Result<Vec<Token>> {
+    let mut scanner = Scanner::new(&expr);
+    let mut tokens = vec![];
+    while let Some(c) = scanner.peek() {
+        let (start, line, col) = (scanner.offset, scanner.line, scanner.col);
+        let text = match c {
+            _ if c.is_whitespace() => {
+                scanner.bump();
+                continue;
+            }
+            // `;` comments run to the end of the line
+            ';' => {
+                while scanner.peek().is_some_and(|c| c != '\n') {
+                    scanner.bump();
+                }
+                continue;
+            }
+            '#' if scanner.peek_second() == Some('|') => {
+                scanner.block_comment()?;
+                continue;
+            }
+            '#' if scanner.peek_second() == Some('\\') => scanner.char_literal(),
+            // `#(` opens a vector literal
+            '#' if scanner.peek_second() == Some('(') => {
+                scanner.bump();
+                scanner.bump();
+                "#(".to_owned()
+            }
+            '(' | ')' | '{' | '}' | '\'' | '`' => {
+                scanner.bump();
+                c.to_string()
+            }
+            // `,` and `,@`
+            ',' => {
+                scanner.bump();
+                match scanner.peek() {
+                    Some('@') => {
+                        scanner.bump();
+                        ",@".to_owned()
+                    }
+                    _ => ",".to_owned(),
+                }
+            }
+            '"' => scanner.string_literal()?,
+            _ => scanner.atom(),
         };
+        tokens.push(Token {
+            text,
+            line,
+            col,
+            span: start..scanner.offset,
+        });
+    }
+    Ok(tokens)
+}
 
-        let exps = vec![Expr::Number(1.0), Expr::Number(2.0), Expr::Number(3.0)];
+fn escape_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
 
-        assert_eq!(add(&exps).unwrap(), Expr::Number(6.0));
-        assert_eq!(sub(&exps).unwrap(), Expr::Number(-4.0));
+fn unescape_string(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some(c) => unescaped.push(c),
+            None => {}
+        }
     }
+    unescaped
+}
 
-    #[test]
-    fn check_eval() {
-        let mut env = default_env();
+// the chars written by name after `#\`, e.g. `#\space`
+const CHAR_NAMES: &[(&str, char)] = &[("space", ' '), ("newline", '\n'), ("tab", '\t')];
 
-        // Expr::List
-        let (exp1, _) = parse(&tokenize("(+ 1 2)".to_owned())).unwrap();
-        let (exp2, _) = parse(&tokenize("(+ 1 (+ 2 3 4))".to_owned())).unwrap();
-        let (exp3, _) = parse(&tokenize("(- 2 3)".to_owned())).unwrap();
-        let (exp4, _) = parse(&tokenize("(- 2 (+ 1 2 3))".to_owned())).unwrap();
-
-        assert_eq!(eval(&exp1, &mut env).unwrap(), Expr::Number(3.0));
-        assert_eq!(eval(&exp2, &mut env).unwrap(), Expr::Number(10.0));
-        assert_eq!(eval(&exp3, &mut env).unwrap(), Expr::Number(-1.0));
-        assert_eq!(eval(&exp4, &mut env).unwrap(), Expr::Number(-4.0));
+// the char a `#\` literal spells, given the text after `#\`
+fn parse_char(literal: &str) -> Option<char> {
+    let mut chars = literal.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => CHAR_NAMES
+            .iter()
+            .find(|(name, _)| *name == literal)
+            .map(|&(_, c)| c),
+    }
+}
+
+/// Parse one expression from `tokens`, returning it with the unconsumed tokens
+pub fn parse(tokens: &[Token]) -> Result<(Expr, &[Token])> {
+    if tokens.is_empty() {
+        return Err(empty_input_error("Expected an expression"));
+    }
+    parse_nested(tokens, 0)
+}
+
+// a parse error for input with no tokens at all
+fn empty_input_error(message: &str) -> anyhow::Error {
+    Error::ParseError {
+        message: message.to_owned(),
+        span: 0..0,
+        line: 1,
+        col: 1,
+    }
+    .into()
+}
+
+// `depth` is the number of `(` still waiting for their `)`; `tokens` is never
+// empty
+fn parse_nested(tokens: &[Token], depth: usize) -> Result<(Expr, &[Token])> {
+    with_stack(|| {
+        let (token, rest) = tokens
+            .split_first()
+            .expect("callers check for a token first");
+        match token.text.as_str() {
+            "(" => read_seq(token, rest, depth + 1, ")")
+                .map(|(items, rest)| (Expr::List(Rc::new(items)), rest)),
+            // vector and dict literals evaluate to themselves, so their items are
+            // taken as written
+            "#(" => read_seq(token, rest, depth + 1, ")")
+                .map(|(items, rest)| (Expr::Vector(Rc::new(items)), rest)),
+            "{" => {
+                let (items, rest) = read_seq(token, rest, depth, "}")?;
+                if !items.len().is_multiple_of(2) {
+                    return Err(token.error("Expected a value for every key in a dict".to_owned()));
+                }
+                let dict = items
+                    .chunks(2)
+                    .map(|pair| Ok((DictKey::try_from(&pair[0])?, pair[1].clone())))
+                    .collect::<Result<HashMap<DictKey, Expr>>>()
+                    .map_err(|err| token.error(err.to_string()))?;
+                Ok((Expr::Dict(Rc::new(dict)), rest))
+            }
+            close @ (")" | "}") => Err(token.error(format!("Unexpected `{close}`"))),
+            // `'expr` is shorthand for `(quote expr)`, and likewise for the
+            // quasiquote prefixes
+            prefix @ ("'" | "`" | "," | ",@") => {
+                let form = match prefix {
+                    "'" => "quote",
+                    "`" => "quasiquote",
+                    "," => "unquote",
+                    _ => "unquote-splicing",
+                };
+                if rest.is_empty() {
+                    return Err(token.error(format!("Expected an expression after `{prefix}`")));
+                }
+                let (quoted, rest) = parse_nested(rest, depth)?;
+                let form = Expr::Symbol(Symbol::new(form));
+                Ok((Expr::List(Rc::new(vec![form, quoted])), rest))
+            }
+            text if text.starts_with("#\\") && parse_char(&text[2..]).is_none() => {
+                Err(token.error(format!("Unknown character `{text}`")))
+            }
+            _ => Ok((parse_atom(&token.text), rest)),
+        }
+    })
+}
+
+// `open` is the `(`, `#(` or `{` token that started this sequence, and
+// `close` the token that ends it
+fn read_seq<'a>(
+    open: &Token,
+    tokens: &'a [Token],
+    depth: usize,
+    close: &str,
+) -> Result<(Vec<Expr>, &'a [Token])> {
+    let mut result: Vec<Expr> = vec![];
+    let mut xs = tokens;
+    loop {
+        let Some((next_token, rest)) = xs.split_first() else {
+            return Err(match close {
+                ")" => Error::UnbalancedParens(depth, open.line, open.col).into(),
+                _ => open.error(format!("Expected `{close}` to close `{}`", open.text)),
+            });
+        };
+        if next_token.text == close {
+            return Ok((result, rest));
+        }
+        let (expr, new_xs) = parse_nested(xs, depth)?;
+        result.push(expr);
+        xs = new_xs;
+    }
+}
+
+// `0x` and `0b` integer literals, optionally negated; there are no hex floats
+fn parse_radix_int(token: &str) -> Option<Num> {
+    let (sign, unsigned) = match token.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", token),
+    };
+    let (radix, digits) = if let Some(digits) = unsigned.strip_prefix("0x") {
+        (16, digits)
+    } else if let Some(digits) = unsigned.strip_prefix("0b") {
+        (2, digits)
+    } else {
+        return None;
+    };
+    // `parse_bytes` would also take a sign after the prefix, and `_`s
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    let n = BigInt::parse_bytes(format!("{}{}", sign, digits).as_bytes(), radix)?;
+    Some(Num::big(n))
+}
+
+// `3/4` or `-6/8`, reduced to lowest terms; the denominator can't be signed
+// or zero
+fn parse_ratio(token: &str) -> Option<Num> {
+    let (num, den) = token.split_once('/')?;
+    if den.is_empty() || !den.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let num: i64 = num.parse().ok()?;
+    let den: i64 = den.parse().ok()?;
+    Num::ratio((num.into(), den.into()))
+}
+
+fn parse_atom(token: &str) -> Expr {
+    if let Some(literal) = token.strip_prefix('"').and_then(|t| t.strip_suffix('"')) {
+        return Expr::String(unescape_string(literal));
+    }
+    if let Some(c) = token.strip_prefix("#\\").and_then(parse_char) {
+        return Expr::Char(c);
+    }
+    match token {
+        "true" => Expr::Bool(true),
+        "false" => Expr::Bool(false),
+        _ => {
+            if let Ok(v) = token.parse() {
+                return Expr::Int(v);
+            }
+            if let Some(n) = parse_radix_int(token) {
+                return n.into();
+            }
+            // integers too big for `i64`; `BigInt` would also take `_`
+            // separators, so check for plain digits first
+            let digits = token.strip_prefix(['-', '+']).unwrap_or(token);
+            if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+                if let Ok(v) = token.parse() {
+                    return Num::big(v).into();
+                }
+            }
+            if let Some(ratio) = parse_ratio(token) {
+                return ratio.into();
+            }
+            // `f64` would also read `inf` and `nan`; those stay symbols so a
+            // float literal always has digits in it
+            let has_digit = token.bytes().any(|b| b.is_ascii_digit());
+            match token.parse() {
+                Ok(v) if has_digit => Expr::Number(v),
+                _ => Expr::Symbol(Symbol::new(token)),
+            }
+        }
+    }
+}
+
+/// Library functions written in Largo itself, evaluated into every
+/// `default_env` on top of the builtins
+pub const PRELUDE: &str = "
+(define square (lambda (x) (* x x)))
+(define cube (lambda (x) (* x x x)))
+(define identity (lambda (x) x))
+(define compose (lambda (f g) (lambda (x) (f (g x)))))
+(define sum (lambda (xs) (fold + 0 xs)))
+(define product (lambda (xs) (fold * 1 xs)))
+(define last (lambda (xs) (if (null? (cdr xs)) (car xs) (last (cdr xs)))))
+(define nth (lambda (xs n) (if (= n 0) (car xs) (nth (cdr xs) (- n 1)))))
+(define remove (lambda (pred xs) (filter (lambda (x) (not (pred x))) xs)))
+(define stream-car (lambda (s) (car s)))
+(define stream-cdr (lambda (s) (force (car (cdr s)))))
+";
+
+/// The root environment holding every builtin and the `PRELUDE`
+pub fn default_env() -> Env {
+    // `data` is a map from symbols to expressions
+    let mut data = HashMap::<Symbol, Expr>::new();
+    data.insert(
+        Symbol::new("+"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let nums = parse_list_of_nums(args)?;
+            let sum = nums.iter().fold(Num::Int(0), |acc, x| acc.add(x));
+            Ok(sum.into())
+        }),
+    );
+    data.insert(
+        Symbol::new("-"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let nums = parse_list_of_nums(args)?;
+            let (first, rest) = nums.split_first().ok_or(Error::Reason(
+                "`-` requires at least one operand".to_owned(),
+            ))?;
+            // a single operand is negated: `(- x)` is `0 - x`
+            let (minuend, subtrahends) = match rest {
+                [] => (Num::Int(0), &nums[..]),
+                _ => (first.clone(), rest),
+            };
+            let difference = subtrahends.iter().fold(minuend, |acc, x| acc.sub(x));
+            Ok(difference.into())
+        }),
+    );
+    data.insert(
+        Symbol::new("inc"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let x = single_arg("inc", args).and_then(parse_single_num)?;
+            Ok(x.add(&Num::Int(1)).into())
+        }),
+    );
+    data.insert(
+        Symbol::new("dec"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let x = single_arg("dec", args).and_then(parse_single_num)?;
+            Ok(x.sub(&Num::Int(1)).into())
+        }),
+    );
+    data.insert(
+        Symbol::new("*"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let nums = parse_list_of_nums(args)?;
+            let product = nums.iter().fold(Num::Int(1), |acc, x| {
+                acc.combine(
+                    x,
+                    i64::checked_mul,
+                    |a, b| Some(a * b),
+                    |(a, c), (b, d)| Some((a * b, c * d)),
+                    |a, b| a * b,
+                )
+            });
+            Ok(product.into())
+        }),
+    );
+    data.insert(
+        Symbol::new("/"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let nums = parse_list_of_nums(args)?;
+            let (first, rest) = nums.split_first().ok_or(Error::Reason(
+                "`/` requires at least one operand".to_owned(),
+            ))?;
+            // a single operand is its own divisor: `(/ x)` is `1 / x`
+            let (dividend, divisors) = match rest {
+                [] => (Num::Int(1), &nums[..]),
+                _ => (first.clone(), rest),
+            };
+            divisors
+                .iter()
+                .try_fold(dividend, |acc, divisor| {
+                    if divisor.as_f64() == 0.0 {
+                        return Err(Error::Reason("division by zero".to_owned()).into());
+                    }
+                    // integer division only stays an integer when it is exact,
+                    // and otherwise makes a ratio
+                    let exact_div = |a: i64, b: i64| match a.checked_rem(b) {
+                        Some(0) => a.checked_div(b),
+                        _ => None,
+                    };
+                    Ok(acc.combine(
+                        divisor,
+                        exact_div,
+                        |a, b| (a % b).is_zero().then(|| a / b),
+                        |(a, c), (b, d)| Some((a * d, c * b)),
+                        |a, b| a / b,
+                    ))
+                })
+                .map(Expr::from)
+        }),
+    );
+    data.insert(
+        Symbol::new(">"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            compare_pairwise(">", args, Ordering::is_gt)
+        }),
+    );
+    data.insert(
+        Symbol::new("<"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            compare_pairwise("<", args, Ordering::is_lt)
+        }),
+    );
+    data.insert(
+        Symbol::new(">="),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            compare_pairwise(">=", args, Ordering::is_ge)
+        }),
+    );
+    data.insert(
+        Symbol::new("<="),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            compare_pairwise("<=", args, Ordering::is_le)
+        }),
+    );
+    data.insert(
+        Symbol::new("="),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            compare_pairwise("=", args, Ordering::is_eq)
+        }),
+    );
+    // `(values a b)` returns both at once, for `let-values` to take apart; a
+    // single value is just itself
+    data.insert(
+        Symbol::new("values"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            Ok(match args {
+                [value] => value.clone(),
+                _ => Expr::Values(Rc::new(args.to_vec())),
+            })
+        }),
+    );
+    // `(divmod a b)` is the floored quotient and the remainder as two values,
+    // where the remainder matches `mod`
+    data.insert(
+        Symbol::new("divmod"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let [dividend, divisor] = &parse_list_of_nums(args)?[..] else {
+                return Err(
+                    Error::Reason("`divmod` expects exactly two operands".to_owned()).into(),
+                );
+            };
+            let (Some(dividend), Some(divisor)) = (dividend.big_int(), divisor.big_int()) else {
+                return Err(Error::Reason("`divmod` expects integers".to_owned()).into());
+            };
+            if divisor.is_zero() {
+                return Err(Error::Reason("division by zero".to_owned()).into());
+            }
+            let (mut quotient, mut remainder) = (&dividend / &divisor, &dividend % &divisor);
+            if !remainder.is_zero() && remainder.is_negative() != divisor.is_negative() {
+                quotient -= 1;
+                remainder += &divisor;
+            }
+            let values = vec![Num::big(quotient).into(), Num::big(remainder).into()];
+            Ok(Expr::Values(Rc::new(values)))
+        }),
+    );
+    // floor modulo: the result takes the sign of the divisor, so `(mod -7 3)`
+    // is `2` and `(mod 7 -3)` is `-2`
+    data.insert(
+        Symbol::new("mod"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let [dividend, divisor] = &parse_list_of_nums(args)?[..] else {
+                return Err(Error::Reason("`mod` expects exactly two operands".to_owned()).into());
+            };
+            if divisor.as_f64() == 0.0 {
+                return Err(Error::Reason("mod by zero".to_owned()).into());
+            }
+            let int_mod = |a: i64, b: i64| {
+                let r = a.checked_rem(b)?;
+                Some(if r != 0 && (r < 0) != (b < 0) {
+                    r + b
+                } else {
+                    r
+                })
+            };
+            let big_mod = |a: &BigInt, b: &BigInt| {
+                let r = a % b;
+                Some(if !r.is_zero() && r.is_negative() != b.is_negative() {
+                    r + b
+                } else {
+                    r
+                })
+            };
+            let float_mod = |a: f64, b: f64| {
+                let r = a % b;
+                if r != 0.0 && (r < 0.0) != (b < 0.0) {
+                    r + b
+                } else {
+                    r
+                }
+            };
+            // `a/c mod b/d` is `(a*d mod b*c) / c*d`
+            let ratio_mod = |(a, c): Fraction, (b, d): Fraction| {
+                let (x, y) = (a * d, b * c);
+                let r = x % y;
+                let r = if r != 0 && (r < 0) != (y < 0) {
+                    r + y
+                } else {
+                    r
+                };
+                Some((r, c * d))
+            };
+            Ok(dividend
+                .combine(divisor, int_mod, big_mod, ratio_mod, float_mod)
+                .into())
+        }),
+    );
+    data.insert(
+        Symbol::new("sqrt"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let x = single_arg("sqrt", args).and_then(parse_single_float)?;
+            if x < 0.0 {
+                return Err(Error::Reason("`sqrt` of a negative number".to_owned()).into());
+            }
+            Ok(Expr::Number(x.sqrt()))
+        }),
+    );
+    data.insert(Symbol::new("pi"), Expr::Number(std::f64::consts::PI));
+    data.insert(
+        Symbol::new("sin"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> { float_fn("sin", args, f64::sin) }),
+    );
+    data.insert(
+        Symbol::new("cos"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> { float_fn("cos", args, f64::cos) }),
+    );
+    data.insert(
+        Symbol::new("tan"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> { float_fn("tan", args, f64::tan) }),
+    );
+    data.insert(
+        Symbol::new("exp"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> { float_fn("exp", args, f64::exp) }),
+    );
+    data.insert(
+        Symbol::new("log"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> { log("log", args, f64::ln) }),
+    );
+    data.insert(
+        Symbol::new("log10"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> { log("log10", args, f64::log10) }),
+    );
+    data.insert(
+        Symbol::new("pow"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let [base, exponent] = &parse_list_of_nums(args)?[..] else {
+                return Err(
+                    Error::Reason("`pow` expects a base and an exponent".to_owned()).into(),
+                );
+            };
+            // integer powers stay exact, unless the result would be too big to
+            // be worth working out
+            let int_pow = match (base.big_int(), exponent) {
+                (Some(b), &Num::Int(e)) => u32::try_from(e)
+                    .ok()
+                    .filter(|&e| b.bits().saturating_mul(e.into()) <= MAX_POW_BITS)
+                    .map(|e| Num::big(b.pow(e))),
+                _ => None,
+            };
+            Ok(int_pow
+                .unwrap_or_else(|| Num::Float(base.as_f64().powf(exponent.as_f64())))
+                .into())
+        }),
+    );
+    data.insert(
+        Symbol::new("abs"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let x = single_arg("abs", args).and_then(parse_single_num)?;
+            Ok(match x {
+                Num::Int(n) => n
+                    .checked_abs()
+                    .map_or_else(|| Num::big(BigInt::from(n).abs()).into(), Expr::Int),
+                Num::Big(n) => Num::big(n.abs()).into(),
+                Num::Ratio(num, den) => num
+                    .checked_abs()
+                    .map_or(Expr::Number((num as f64 / den as f64).abs()), |num| {
+                        Expr::Ratio { num, den }
+                    }),
+                Num::Float(n) => Expr::Number(n.abs()),
+            })
+        }),
+    );
+    data.insert(
+        Symbol::new("floor"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            round_with("floor", args, i128::div_euclid, f64::floor)
+        }),
+    );
+    data.insert(
+        Symbol::new("ceil"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            round_with("ceil", args, |num, den| -(-num).div_euclid(den), f64::ceil)
+        }),
+    );
+    data.insert(
+        Symbol::new("truncate"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            round_with("truncate", args, |num, den| num / den, f64::trunc)
+        }),
+    );
+    // halfway cases go to the even neighbour, as in Scheme: `(round 2.5)` is
+    // `2` and `(round 3.5)` is `4`
+    data.insert(
+        Symbol::new("round"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let round_ratio = |num: i128, den: i128| {
+                let floor = num.div_euclid(den);
+                let twice_rest = 2 * num.rem_euclid(den);
+                match twice_rest.cmp(&den) {
+                    Ordering::Less => floor,
+                    Ordering::Greater => floor + 1,
+                    Ordering::Equal => floor + floor.rem_euclid(2),
+                }
+            };
+            round_with("round", args, round_ratio, f64::round_ties_even)
+        }),
+    );
+    data.insert(
+        Symbol::new("min"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> { extremum("min", args, Ordering::is_lt) }),
+    );
+    data.insert(
+        Symbol::new("max"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> { extremum("max", args, Ordering::is_gt) }),
+    );
+    // `(clamp value lo hi)` is `value`, moved into `[lo, hi]` if it is outside
+    data.insert(
+        Symbol::new("clamp"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let [value, lo, hi] = &parse_list_of_nums(args)?[..] else {
+                return Err(Error::Reason(
+                    "`clamp` expects a value, a minimum and a maximum".to_owned(),
+                )
+                .into());
+            };
+            if lo.compare(hi).is_none_or(Ordering::is_gt) {
+                return Err(Error::Reason(format!(
+                    "`clamp` bounds are out of order: `{}` is above `{}`",
+                    Expr::from(lo.clone()),
+                    Expr::from(hi.clone())
+                ))
+                .into());
+            }
+            let clamped = if value.compare(lo).is_some_and(Ordering::is_lt) {
+                lo
+            } else if value.compare(hi).is_some_and(Ordering::is_gt) {
+                hi
+            } else {
+                value
+            };
+            Ok(clamped.clone().into())
+        }),
+    );
+    data.insert(
+        Symbol::new("bit-and"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let ints = parse_list_of_ints("bit-and", args)?;
+            Ok(Expr::Int(ints.into_iter().fold(-1, |acc, x| acc & x)))
+        }),
+    );
+    data.insert(
+        Symbol::new("bit-or"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let ints = parse_list_of_ints("bit-or", args)?;
+            Ok(Expr::Int(ints.into_iter().fold(0, |acc, x| acc | x)))
+        }),
+    );
+    data.insert(
+        Symbol::new("bit-xor"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let ints = parse_list_of_ints("bit-xor", args)?;
+            Ok(Expr::Int(ints.into_iter().fold(0, |acc, x| acc ^ x)))
+        }),
+    );
+    data.insert(
+        Symbol::new("bit-not"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let [n] = parse_list_of_ints("bit-not", args)?[..] else {
+                return Err(
+                    Error::Reason("`bit-not` expects exactly one operand".to_owned()).into(),
+                );
+            };
+            Ok(Expr::Int(!n))
+        }),
+    );
+    data.insert(
+        Symbol::new("shift-left"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> { shift("shift-left", args, i64::checked_shl) }),
+    );
+    // an arithmetic shift, so negative numbers stay negative
+    data.insert(
+        Symbol::new("shift-right"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            shift("shift-right", args, i64::checked_shr)
+        }),
+    );
+    data.insert(
+        Symbol::new("equal?"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let [a, b] = args else {
+                return Err(
+                    Error::Reason("`equal?` expects exactly two arguments".to_owned()).into(),
+                );
+            };
+            Ok(Expr::Bool(a == b))
+        }),
+    );
+    // for tests written in Largo; a builtin only sees the value of its
+    // argument, so the error can't quote the expression that failed
+    data.insert(
+        Symbol::new("assert"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            if !is_truthy(single_arg("assert", args)?) {
+                return Err(Error::Reason("assertion failed".to_owned()).into());
+            }
+            Ok(Expr::nil())
+        }),
+    );
+    // compares like `equal?`
+    data.insert(
+        Symbol::new("assert-eq"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let [a, b] = args else {
+                return Err(
+                    Error::Reason("`assert-eq` expects exactly two arguments".to_owned()).into(),
+                );
+            };
+            if a != b {
+                return Err(Error::Reason(format!(
+                    "assertion failed: `{a}` is not equal to `{b}`"
+                ))
+                .into());
+            }
+            Ok(Expr::nil())
+        }),
+    );
+    // `(error "message")` fails with that message, which `try` can catch
+    data.insert(
+        Symbol::new("error"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let [Expr::String(message)] = args else {
+                return Err(
+                    Error::Reason("`error` expects a single message string".to_owned()).into(),
+                );
+            };
+            Err(Error::UserError(message.clone()).into())
+        }),
+    );
+    data.insert(
+        Symbol::new("list"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> { Ok(Expr::List(Rc::new(args.to_vec()))) }),
+    );
+    data.insert(
+        Symbol::new("cons"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let [head, Expr::List(tail)] = args else {
+                return Err(
+                    Error::Reason("`cons` expects an element and a list".to_owned()).into(),
+                );
+            };
+            let mut list = Vec::with_capacity(tail.len() + 1);
+            list.push(head.clone());
+            list.extend_from_slice(tail);
+            Ok(Expr::List(Rc::new(list)))
+        }),
+    );
+    data.insert(Symbol::new("nil"), Expr::nil());
+    data.insert(
+        Symbol::new("null?"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let arg = single_arg("null?", args)?;
+            Ok(Expr::Bool(
+                matches!(arg, Expr::List(list) if list.is_empty()),
+            ))
+        }),
+    );
+    data.insert(
+        Symbol::new("length"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let len = match single_arg("length", args)? {
+                Expr::List(list) => list.len(),
+                Expr::String(string) => string.chars().count(),
+                arg => {
+                    return Err(Error::Reason(format!(
+                        "`length` expects a list or a string, got `{arg}`"
+                    ))
+                    .into())
+                }
+            };
+            Ok(Expr::Int(len as i64))
+        }),
+    );
+    data.insert(
+        Symbol::new("append"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let mut joined = vec![];
+            for arg in args {
+                let Expr::List(list) = arg else {
+                    return Err(
+                        Error::Reason(format!("`append` expects lists, got `{arg}`")).into(),
+                    );
+                };
+                joined.extend_from_slice(list);
+            }
+            Ok(Expr::List(Rc::new(joined)))
+        }),
+    );
+    data.insert(
+        Symbol::new("reverse"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let [Expr::List(list)] = args else {
+                return Err(Error::Reason("`reverse` expects a single list".to_owned()).into());
+            };
+            Ok(Expr::List(Rc::new(list.iter().rev().cloned().collect())))
+        }),
+    );
+    let car = Expr::Func(|args: &[Expr]| -> Result<Expr> {
+        let [Expr::List(list)] = args else {
+            return Err(Error::Reason("`car` expects a single list".to_owned()).into());
+        };
+        list.first()
+            .cloned()
+            .ok_or(Error::Reason("`car` of an empty list".to_owned()).into())
+    });
+    data.insert(Symbol::new("car"), car.clone());
+    data.insert(Symbol::new("first"), car);
+    let cdr = Expr::Func(|args: &[Expr]| -> Result<Expr> {
+        let [Expr::List(list)] = args else {
+            return Err(Error::Reason("`cdr` expects a single list".to_owned()).into());
+        };
+        let (_, tail) = list
+            .split_first()
+            .ok_or(Error::Reason("`cdr` of an empty list".to_owned()))?;
+        Ok(Expr::List(Rc::new(tail.to_vec())))
+    });
+    data.insert(
+        Symbol::new("not"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let [arg] = args else {
+                return Err(Error::Reason("`not` expects exactly one argument".to_owned()).into());
+            };
+            Ok(Expr::Bool(!is_truthy(arg)))
+        }),
+    );
+    // the higher-order builtins hand the caller's scope on to whatever they
+    // call, so env-aware builtins work through them too
+    data.insert(
+        Symbol::new("map"),
+        Expr::EnvFunc(|args: &[Expr], env: &mut Env| -> Result<Expr> {
+            let [func, list] = args else {
+                return Err(Error::Reason("`map` expects a function and a list".to_owned()).into());
+            };
+            let Expr::List(list) = list else {
+                return Err(Error::Reason(
+                    "`map` expects a list as its second argument".to_owned(),
+                )
+                .into());
+            };
+            list.iter()
+                .map(|x| apply(func, vec![x.clone()], env))
+                .collect::<Result<Vec<Expr>>>()
+                .map(|list| Expr::List(Rc::new(list)))
+        }),
+    );
+    data.insert(
+        Symbol::new("filter"),
+        Expr::EnvFunc(|args: &[Expr], env: &mut Env| -> Result<Expr> {
+            let [pred, Expr::List(list)] = args else {
+                return Err(
+                    Error::Reason("`filter` expects a predicate and a list".to_owned()).into(),
+                );
+            };
+            let mut kept = vec![];
+            for x in list.iter() {
+                if is_truthy(&apply(pred, vec![x.clone()], env)?) {
+                    kept.push(x.clone());
+                }
+            }
+            Ok(Expr::List(Rc::new(kept)))
+        }),
+    );
+    // `(fold f init list)` calls `(f acc x)` for each element from the left
+    let fold = Expr::EnvFunc(|args: &[Expr], env: &mut Env| -> Result<Expr> {
+        let [func, init, Expr::List(list)] = args else {
+            return Err(Error::Reason(
+                "`fold` expects a function, an initial value and a list".to_owned(),
+            )
+            .into());
+        };
+        list.iter().try_fold(init.clone(), |acc, x| {
+            apply(func, vec![acc, x.clone()], env)
+        })
+    });
+    data.insert(Symbol::new("fold"), fold.clone());
+    data.insert(Symbol::new("reduce"), fold);
+    data.insert(
+        Symbol::new("apply"),
+        Expr::EnvFunc(|args: &[Expr], env: &mut Env| -> Result<Expr> {
+            let [func, list] = args else {
+                return Err(
+                    Error::Reason("`apply` expects a function and a list".to_owned()).into(),
+                );
+            };
+            if !is_callable(func) {
+                return Err(Error::NotCallable(func.to_string()).into());
+            }
+            let Expr::List(list) = list else {
+                return Err(Error::Reason(format!(
+                    "`apply` expects a list of arguments, got `{list}`"
+                ))
+                .into());
+            };
+            apply(func, list.to_vec(), env)
+        }),
+    );
+    data.insert(
+        Symbol::new("string-concat"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let mut joined = String::new();
+            for arg in args {
+                let Expr::String(string) = arg else {
+                    return Err(Error::Reason(format!(
+                        "`string-concat` expects strings, got `{arg}`"
+                    ))
+                    .into());
+                };
+                joined.push_str(string);
+            }
+            Ok(Expr::String(joined))
+        }),
+    );
+    // `(substring s start end)` takes the chars from `start` up to `end`
+    data.insert(
+        Symbol::new("substring"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let [Expr::String(string), Expr::Int(start), Expr::Int(end)] = args else {
+                return Err(Error::Reason(
+                    "`substring` expects a string, a start and an end index".to_owned(),
+                )
+                .into());
+            };
+            let len = string.chars().count();
+            let (Ok(from), Ok(to)) = (usize::try_from(*start), usize::try_from(*end)) else {
+                return Err(Error::Reason(format!(
+                    "`substring` range {start}..{end} is out of bounds for length {len}"
+                ))
+                .into());
+            };
+            if from > to || to > len {
+                return Err(Error::Reason(format!(
+                    "`substring` range {start}..{end} is out of bounds for length {len}"
+                ))
+                .into());
+            }
+            Ok(Expr::String(
+                string.chars().skip(from).take(to - from).collect(),
+            ))
+        }),
+    );
+    data.insert(
+        Symbol::new("string-length"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let [Expr::String(string)] = args else {
+                return Err(
+                    Error::Reason("`string-length` expects a single string".to_owned()).into(),
+                );
+            };
+            Ok(Expr::Int(string.chars().count() as i64))
+        }),
+    );
+    data.insert(
+        Symbol::new("number->string"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let num = single_arg("number->string", args).and_then(parse_single_num)?;
+            Ok(Expr::String(Expr::from(num).to_string()))
+        }),
+    );
+    // reads a number the way the parser would, giving `false` rather than an
+    // error when the string isn't one, so callers can test the result
+    data.insert(
+        Symbol::new("string->number"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let [Expr::String(string)] = args else {
+                return Err(
+                    Error::Reason("`string->number` expects a single string".to_owned()).into(),
+                );
+            };
+            Ok(match parse_atom(string) {
+                num @ (Expr::Int(_) | Expr::BigInt(_) | Expr::Ratio { .. } | Expr::Number(_)) => {
+                    num
+                }
+                _ => Expr::Bool(false),
+            })
+        }),
+    );
+    data.insert(
+        Symbol::new("vector"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> { Ok(Expr::Vector(Rc::new(args.to_vec()))) }),
+    );
+    data.insert(
+        Symbol::new("vector-ref"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let [Expr::Vector(vector), Expr::Int(index)] = args else {
+                return Err(
+                    Error::Reason("`vector-ref` expects a vector and an index".to_owned()).into(),
+                );
+            };
+            usize::try_from(*index)
+                .ok()
+                .and_then(|i| vector.get(i))
+                .cloned()
+                .ok_or(
+                    Error::Reason(format!(
+                        "`vector-ref` index {index} is out of bounds for length {}",
+                        vector.len()
+                    ))
+                    .into(),
+                )
+        }),
+    );
+    data.insert(
+        Symbol::new("vector-length"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let [Expr::Vector(vector)] = args else {
+                return Err(
+                    Error::Reason("`vector-length` expects a single vector".to_owned()).into(),
+                );
+            };
+            Ok(Expr::Int(vector.len() as i64))
+        }),
+    );
+    // `(make-dict key value...)` builds a dict from alternating keys and values
+    data.insert(
+        Symbol::new("make-dict"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            if !args.len().is_multiple_of(2) {
+                return Err(Error::Reason(
+                    "`make-dict` expects alternating keys and values".to_owned(),
+                )
+                .into());
+            }
+            let dict = args
+                .chunks(2)
+                .map(|pair| Ok((DictKey::try_from(&pair[0])?, pair[1].clone())))
+                .collect::<Result<HashMap<DictKey, Expr>>>()?;
+            Ok(Expr::Dict(Rc::new(dict)))
+        }),
+    );
+    // a missing key gives nil rather than an error
+    data.insert(
+        Symbol::new("dict-get"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let [Expr::Dict(dict), key] = args else {
+                return Err(Error::Reason("`dict-get` expects a dict and a key".to_owned()).into());
+            };
+            let key = DictKey::try_from(key)?;
+            Ok(dict.get(&key).cloned().unwrap_or(Expr::nil()))
+        }),
+    );
+    // dicts are values like lists, so `dict-set` returns an updated copy and
+    // leaves the original alone
+    data.insert(
+        Symbol::new("dict-set"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let [Expr::Dict(dict), key, value] = args else {
+                return Err(Error::Reason(
+                    "`dict-set` expects a dict, a key and a value".to_owned(),
+                )
+                .into());
+            };
+            let mut dict = HashMap::clone(dict);
+            dict.insert(DictKey::try_from(key)?, value.clone());
+            Ok(Expr::Dict(Rc::new(dict)))
+        }),
+    );
+    // writes its arguments to the env's output, separated by spaces and
+    // without a newline
+    let print = Expr::EnvFunc(|args: &[Expr], env: &mut Env| -> Result<Expr> {
+        env.write_output(&display_text(args))?;
+        Ok(Expr::nil())
+    });
+    data.insert(Symbol::new("print"), print.clone());
+    data.insert(Symbol::new("display"), print);
+    // like `print`, but in the form `read` takes back: strings keep their
+    // quotes and escapes; functions, macros, promises and `values` have no
+    // such form
+    data.insert(
+        Symbol::new("write"),
+        Expr::EnvFunc(|args: &[Expr], env: &mut Env| -> Result<Expr> {
+            env.write_output(&write_text(args))?;
+            Ok(Expr::nil())
+        }),
+    );
+    data.insert(
+        Symbol::new("repr"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let expr = single_arg("repr", args)?;
+            Ok(Expr::String(expr.to_string()))
+        }),
+    );
+    data.insert(
+        Symbol::new("newline"),
+        Expr::EnvFunc(|args: &[Expr], env: &mut Env| -> Result<Expr> {
+            if !args.is_empty() {
+                return Err(Error::Reason("`newline` takes no arguments".to_owned()).into());
+            }
+            env.write_output("\n")?;
+            Ok(Expr::nil())
+        }),
+    );
+    data.insert(
+        Symbol::new("char->number"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let [Expr::Char(c)] = args else {
+                return Err(
+                    Error::Reason("`char->number` expects a single char".to_owned()).into(),
+                );
+            };
+            Ok(Expr::Int(u32::from(*c).into()))
+        }),
+    );
+    data.insert(
+        Symbol::new("number->char"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let [Expr::Int(n)] = args else {
+                return Err(
+                    Error::Reason("`number->char` expects a single integer".to_owned()).into(),
+                );
+            };
+            u32::try_from(*n)
+                .ok()
+                .and_then(char::from_u32)
+                .map(Expr::Char)
+                .ok_or(Error::Reason(format!("`{n}` is not a valid char")).into())
+        }),
+    );
+    data.insert(
+        Symbol::new("type-of"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let arg = single_arg("type-of", args)?;
+            Ok(Expr::Symbol(Symbol::new(arg.type_name())))
+        }),
+    );
+    data.insert(
+        Symbol::new("zero?"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> { sign_test("zero?", args, Ordering::is_eq) }),
+    );
+    data.insert(
+        Symbol::new("positive?"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            sign_test("positive?", args, Ordering::is_gt)
+        }),
+    );
+    data.insert(
+        Symbol::new("negative?"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            sign_test("negative?", args, Ordering::is_lt)
+        }),
+    );
+    data.insert(
+        Symbol::new("even?"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> { Ok(Expr::Bool(is_even("even?", args)?)) }),
+    );
+    data.insert(
+        Symbol::new("odd?"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> { Ok(Expr::Bool(!is_even("odd?", args)?)) }),
+    );
+    data.insert(
+        Symbol::new("number?"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> { has_type("number?", "number", args) }),
+    );
+    data.insert(
+        Symbol::new("symbol?"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> { has_type("symbol?", "symbol", args) }),
+    );
+    data.insert(
+        Symbol::new("list?"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> { has_type("list?", "list", args) }),
+    );
+    data.insert(
+        Symbol::new("string?"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> { has_type("string?", "string", args) }),
+    );
+    data.insert(
+        Symbol::new("bool?"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> { has_type("bool?", "bool", args) }),
+    );
+    data.insert(
+        Symbol::new("function?"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> { has_type("function?", "function", args) }),
+    );
+    // a fresh symbol on every call, `g__0`, `g__1` and so on, for generated code
+    // that must not capture the names around it
+    data.insert(
+        Symbol::new("gensym"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            if !args.is_empty() {
+                return Err(Error::Reason("`gensym` takes no arguments".to_owned()).into());
+            }
+            let id = NEXT.fetch_add(1, atomic::Ordering::Relaxed);
+            Ok(Expr::Symbol(Symbol::new(&format!("g__{id}"))))
+        }),
+    );
+    // evaluates a promise the first time and hands back that same value after;
+    // anything that isn't a promise is already a value, so it is returned as is
+    data.insert(
+        Symbol::new("force"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let Expr::Promise(promise) = single_arg("force", args)? else {
+                return Ok(args[0].clone());
+            };
+            // not borrowed while evaluating, as the expression may force itself
+            let (delayed, mut env) = match &*promise.borrow() {
+                Promise::Forced(value) => return Ok(value.clone()),
+                Promise::Delayed(delayed, env) => (delayed.clone(), env.clone()),
+            };
+            let value = eval(&delayed, &mut env)?;
+            let mut promise = promise.borrow_mut();
+            // a nested `force` of the same promise may have finished first
+            if let Promise::Forced(value) = &*promise {
+                return Ok(value.clone());
+            }
+            *promise = Promise::Forced(value.clone());
+            Ok(value)
+        }),
+    );
+    // `(eval expr)` evaluates an already evaluated value again, in the scope
+    // `eval` was called from, so `(eval '(+ 1 2))` is `3`
+    data.insert(
+        Symbol::new("eval"),
+        Expr::EnvFunc(|args: &[Expr], env: &mut Env| -> Result<Expr> {
+            let expr = single_arg("eval", args)?;
+            eval(expr, env)
+        }),
+    );
+    // `(read "(+ 1 2)")` is the list `(+ 1 2)`, parsed but not evaluated
+    data.insert(
+        Symbol::new("read"),
+        Expr::Func(|args: &[Expr]| -> Result<Expr> {
+            let [Expr::String(source)] = args else {
+                return Err(Error::Reason("`read` expects a single string".to_owned()).into());
+            };
+            read_str(source)
+        }),
+    );
+    data.insert(Symbol::new("cdr"), cdr.clone());
+    data.insert(Symbol::new("rest"), cdr);
+    let mut env = Env::new(data, None);
+    // the prelude ships with the interpreter, so an error in it is a bug
+    eval_program(PRELUDE, &mut env).unwrap_or_else(|err| panic!("Error in the prelude: {err:#}"));
+    env
+}
+
+// what `print` writes for `args`: strings without their quotes, anything
+// else as it displays
+fn display_text(args: &[Expr]) -> String {
+    let texts: Vec<String> = args
+        .iter()
+        .map(|arg| match arg {
+            Expr::String(s) => s.clone(),
+            Expr::Char(c) => c.to_string(),
+            arg => arg.to_string(),
+        })
+        .collect();
+    texts.join(" ")
+}
+
+// what `write` writes for `args`: each one as it displays
+fn write_text(args: &[Expr]) -> String {
+    let texts: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+    texts.join(" ")
+}
+
+// the only argument of a unary builtin
+fn single_arg<'a>(name: &str, args: &'a [Expr]) -> Result<&'a Expr> {
+    match args {
+        [arg] => Ok(arg),
+        _ => Err(Error::ArityMismatch {
+            callee: format!("`{name}`"),
+            min: 1,
+            max: Some(1),
+            got: args.len(),
+        }
+        .into()),
+    }
+}
+
+// whether the single numeric argument compares to zero as `test` wants; NaN
+// passes no test
+fn sign_test(name: &str, args: &[Expr], test: fn(Ordering) -> bool) -> Result<Expr> {
+    let x = single_arg(name, args).and_then(parse_single_num)?;
+    Ok(Expr::Bool(x.compare(&Num::Int(0)).is_some_and(test)))
+}
+
+// whether the single argument is even; it must be a whole number, though it
+// may be a float such as `4.0`
+fn is_even(name: &str, args: &[Expr]) -> Result<bool> {
+    let x = single_arg(name, args).and_then(parse_single_num)?;
+    match x {
+        Num::Int(n) => Ok(n % 2 == 0),
+        // the lowest bit is the same in two's complement
+        Num::Big(n) => Ok(!n.bit(0)),
+        Num::Float(n) if n.fract() == 0.0 => Ok(n % 2.0 == 0.0),
+        Num::Ratio(..) | Num::Float(_) => Err(Error::Reason(format!(
+            "`{name}` expects a whole number, got `{}`",
+            Expr::from(x)
+        ))
+        .into()),
+    }
+}
+
+// whether the single argument of the predicate `name` is of type `type_name`,
+// as `type-of` would name it
+fn has_type(name: &str, type_name: &str, args: &[Expr]) -> Result<Expr> {
+    let arg = single_arg(name, args)?;
+    Ok(Expr::Bool(arg.type_name() == type_name))
+}
+
+// `(name n by)` shifts `n` by `by` bits, which must be less than 64
+fn shift(name: &str, args: &[Expr], op: fn(i64, u32) -> Option<i64>) -> Result<Expr> {
+    let [n, by] = parse_list_of_ints(name, args)?[..] else {
+        return Err(Error::Reason(format!("`{name}` expects a number and a shift")).into());
+    };
+    u32::try_from(by)
+        .ok()
+        .and_then(|by| op(n, by))
+        .map(Expr::Int)
+        .ok_or(Error::Reason(format!("`{name}` cannot shift by {by} bits")).into())
+}
+
+// `f` of the single numeric argument, always as a float
+fn float_fn(name: &str, args: &[Expr], f: fn(f64) -> f64) -> Result<Expr> {
+    let x = single_arg(name, args).and_then(parse_single_float)?;
+    Ok(Expr::Number(f(x)))
+}
+
+// the single numeric argument as a whole number, always an integer: integers
+// are already whole, ratios are rounded exactly by `for_ratio` (given the
+// numerator and the positive denominator) and floats by `for_float`
+fn round_with(
+    name: &str,
+    args: &[Expr],
+    for_ratio: fn(i128, i128) -> i128,
+    for_float: fn(f64) -> f64,
+) -> Result<Expr> {
+    let x = single_arg(name, args).and_then(parse_single_num)?;
+    let rounded = match x {
+        Num::Int(_) | Num::Big(_) => x,
+        Num::Ratio(num, den) => Num::big(for_ratio(num.into(), den.into()).into()),
+        Num::Float(n) => BigInt::from_f64(for_float(n))
+            .map(Num::big)
+            .ok_or(Error::Reason(format!(
+                "`{name}` of {n:?}, which has no whole value"
+            )))?,
+    };
+    Ok(rounded.into())
+}
+
+// like `float_fn`, but only for the positive numbers a logarithm is defined on
+fn log(name: &str, args: &[Expr], f: fn(f64) -> f64) -> Result<Expr> {
+    let x = single_arg(name, args).and_then(parse_single_float)?;
+    if x <= 0.0 {
+        return Err(Error::Reason(format!("`{name}` of a non-positive number")).into());
+    }
+    Ok(Expr::Number(f(x)))
+}
+
+// the operand that `replaces` prefers over every other, e.g. the largest for
+// `max`; `replaces` is given how a candidate compares to the best so far
+fn extremum(name: &str, args: &[Expr], replaces: fn(Ordering) -> bool) -> Result<Expr> {
+    let nums = parse_list_of_nums(args)?;
+    let (first, rest) = nums.split_first().ok_or(Error::Reason(format!(
+        "`{name}` requires at least one operand"
+    )))?;
+    let best = rest.iter().fold(first, |best, x| {
+        if x.compare(best).is_some_and(replaces) {
+            x
+        } else {
+            best
+        }
+    });
+    Ok(best.clone().into())
+}
+
+// true when `cmp` holds for every adjacent pair, e.g. `(< 1 2 3)`
+fn compare_pairwise(name: &str, args: &[Expr], cmp: fn(Ordering) -> bool) -> Result<Expr> {
+    let nums = parse_list_of_nums(args)?;
+    if nums.len() < 2 {
+        return Err(Error::Reason(format!("`{name}` requires at least two operands")).into());
+    }
+    let holds = nums
+        .windows(2)
+        .all(|pair| pair[0].compare(&pair[1]).is_some_and(cmp));
+    Ok(Expr::Bool(holds))
+}
+
+// a numeric operand; arithmetic stays exact (in integers, growing into
+// bigints as needed, or in ratios once one is involved) until a float is
+// involved
+#[derive(Clone, Debug, PartialEq)]
+enum Num {
+    Int(i64),
+    // only for integers that don't fit in an `Int`
+    Big(Rc<BigInt>),
+    // in lowest terms, with a denominator above 1
+    Ratio(i64, i64),
+    Float(f64),
+}
+
+// a numerator and denominator, wide enough that multiplying two `i64`s can't
+// overflow
+type Fraction = (i128, i128);
+
+// the largest result `pow` works out exactly, in bits
+const MAX_POW_BITS: u64 = 1 << 20;
+
+impl Num {
+    // `n` as an `Int` if it fits
+    fn big(n: BigInt) -> Num {
+        match i64::try_from(&n) {
+            Ok(n) => Num::Int(n),
+            Err(_) => Num::Big(Rc::new(n)),
+        }
+    }
+
+    // `num / den` in lowest terms, or `None` if it doesn't fit in `i64`s or
+    // the denominator is zero
+    fn ratio((num, den): Fraction) -> Option<Num> {
+        if den == 0 {
+            return None;
+        }
+        let divisor = gcd(num, den) * den.signum();
+        let (num, den) = (
+            i64::try_from(num / divisor).ok()?,
+            i64::try_from(den / divisor).ok()?,
+        );
+        Some(if den == 1 {
+            Num::Int(num)
+        } else {
+            Num::Ratio(num, den)
+        })
+    }
+
+    fn as_f64(&self) -> f64 {
+        match self {
+            Num::Int(n) => *n as f64,
+            Num::Big(n) => n.to_f64().unwrap_or(f64::NAN),
+            Num::Ratio(num, den) => *num as f64 / *den as f64,
+            Num::Float(n) => *n,
+        }
+    }
+
+    // the value as a bigint, if it is a whole number
+    fn big_int(&self) -> Option<BigInt> {
+        match self {
+            Num::Int(n) => Some(BigInt::from(*n)),
+            Num::Big(n) => Some(BigInt::clone(n)),
+            Num::Ratio(..) | Num::Float(_) => None,
+        }
+    }
+
+    // the exact value as a fraction; bigints don't fit one and floats have none
+    fn fraction(&self) -> Option<Fraction> {
+        match self {
+            Num::Int(n) => Some(((*n).into(), 1)),
+            Num::Ratio(num, den) => Some(((*num).into(), (*den).into())),
+            Num::Big(_) | Num::Float(_) => None,
+        }
+    }
+
+    // `int_op` handles two ints, `big_op` ints where one doesn't fit in an
+    // `i64` (or the result doesn't) and `ratio_op` ints and ratios, as well as
+    // ints whose quotient isn't whole; each returns `None` when the result
+    // isn't exact or doesn't fit, falling back to floats, as does a bigint
+    // meeting a ratio
+    fn combine(
+        &self,
+        other: &Num,
+        int_op: fn(i64, i64) -> Option<i64>,
+        big_op: fn(&BigInt, &BigInt) -> Option<BigInt>,
+        ratio_op: fn(Fraction, Fraction) -> Option<Fraction>,
+        float_op: fn(f64, f64) -> f64,
+    ) -> Num {
+        let exact = match (self, other) {
+            (&Num::Int(a), &Num::Int(b)) => int_op(a, b)
+                .map(Num::Int)
+                .or_else(|| big_op(&a.into(), &b.into()).map(Num::big))
+                .or_else(|| ratio_op((a.into(), 1), (b.into(), 1)).and_then(Num::ratio)),
+            (Num::Big(_), Num::Int(_) | Num::Big(_)) | (Num::Int(_), Num::Big(_)) => {
+                self.big_int().zip(other.big_int()).and_then(|(a, b)| {
+                    big_op(&a, &b).map(Num::big).or_else(|| {
+                        let (a, b) = (a.to_i128()?, b.to_i128()?);
+                        ratio_op((a, 1), (b, 1)).and_then(Num::ratio)
+                    })
+                })
+            }
+            _ => self
+                .fraction()
+                .zip(other.fraction())
+                .and_then(|(a, b)| ratio_op(a, b))
+                .and_then(Num::ratio),
+        };
+        exact.unwrap_or_else(|| Num::Float(float_op(self.as_f64(), other.as_f64())))
+    }
+
+    fn add(&self, other: &Num) -> Num {
+        self.combine(
+            other,
+            i64::checked_add,
+            |a, b| Some(a + b),
+            |(a, c), (b, d)| Some(((a * d).checked_add(b * c)?, c * d)),
+            |a, b| a + b,
+        )
+    }
+
+    fn sub(&self, other: &Num) -> Num {
+        self.combine(
+            other,
+            i64::checked_sub,
+            |a, b| Some(a - b),
+            |(a, c), (b, d)| Some(((a * d).checked_sub(b * c)?, c * d)),
+            |a, b| a - b,
+        )
+    }
+
+    // exact unless a float is involved; `None` when either is NaN
+    fn compare(&self, other: &Num) -> Option<Ordering> {
+        if let (Some((a, c)), Some((b, d))) = (self.fraction(), other.fraction()) {
+            // denominators are positive, so cross-multiplying keeps the order
+            return Some((a * d).cmp(&(b * c)));
+        }
+        match (self.big_fraction(), other.big_fraction()) {
+            (Some((a, c)), Some((b, d))) => Some((a * d).cmp(&(b * c))),
+            _ => self.as_f64().partial_cmp(&other.as_f64()),
+        }
+    }
+
+    // like `fraction`, for comparisons involving bigints
+    fn big_fraction(&self) -> Option<(BigInt, BigInt)> {
+        match self {
+            Num::Ratio(num, den) => Some((BigInt::from(*num), BigInt::from(*den))),
+            _ => Some((self.big_int()?, BigInt::from(1))),
+        }
+    }
+}
+
+// the greatest common divisor, never negative; `gcd(0, 0)` is 1 so it can
+// always be divided by
+fn gcd(a: i128, b: i128) -> i128 {
+    let (mut a, mut b) = (a.unsigned_abs(), b.unsigned_abs());
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    i128::try_from(a.max(1)).unwrap_or(i128::MAX)
+}
+
+impl From<Num> for Expr {
+    fn from(num: Num) -> Expr {
+        match num {
+            Num::Int(n) => Expr::Int(n),
+            Num::Big(n) => Expr::BigInt(n),
+            Num::Ratio(num, den) => Expr::Ratio { num, den },
+            Num::Float(n) => Expr::Number(n),
+        }
+    }
+}
+
+fn parse_list_of_nums(nums: &[Expr]) -> Result<Vec<Num>> {
+    nums.iter().map(parse_single_num).collect()
+}
+
+fn parse_single_num(expr: &Expr) -> Result<Num> {
+    match expr {
+        Expr::Int(num) => Ok(Num::Int(*num)),
+        Expr::BigInt(num) => Ok(Num::Big(num.clone())),
+        Expr::Ratio { num, den } => Ok(Num::Ratio(*num, *den)),
+        Expr::Number(num) => Ok(Num::Float(*num)),
+        _ => Err(Error::TypeError {
+            expected: "number".to_owned(),
+            got: expr.type_name().to_owned(),
+        })?,
+    }
+}
+
+// operands of the bitwise builtins, which only make sense for integers
+fn parse_list_of_ints(name: &str, ints: &[Expr]) -> Result<Vec<i64>> {
+    ints.iter()
+        .map(|expr| match expr {
+            Expr::Int(n) => Ok(*n),
+            _ => Err(Error::Reason(format!("`{name}` expects integers, got `{expr}`")).into()),
+        })
+        .collect()
+}
+
+fn parse_single_float(expr: &Expr) -> Result<f64> {
+    parse_single_num(expr).map(|num| num.as_f64())
+}
+
+// what is left to do once a list has been evaluated
+enum Step {
+    Done(Expr),
+    // evaluate this in place of the current expression, without recursing
+    Tail(Rc<Expr>, Env),
+}
+
+/// Evaluate `expr` in `env`
+///
+/// Tail positions (the body of a lambda or `let`, the taken branch of `if`,
+/// the last form of `begin`) loop instead of recursing, so they don't grow
+/// the stack or count towards the recursion depth
+///
+/// While tracing (see `Env::set_trace`), each list is reported as it is
+/// evaluated, along with its value or error; tail positions are reported as
+/// part of the form they belong to
+pub fn eval(expr: &Expr, env: &mut Env) -> Result<Expr> {
+    let _depth = DepthGuard::enter(&env.depth)?;
+    with_stack(|| eval_traced(expr, env))
+}
+
+// `eval` once the depth has been entered, reporting the step if tracing
+fn eval_traced(expr: &Expr, env: &mut Env) -> Result<Expr> {
+    let traced = matches!(expr, Expr::List(_)) && env.trace.borrow().is_some();
+    if !traced {
+        return eval_loop(expr, env);
+    }
+    env.trace_line(format_args!("{expr}"));
+    let result = eval_loop(expr, env);
+    match &result {
+        Ok(value) => env.trace_line(format_args!("=> {value}")),
+        Err(err) => env.trace_line(format_args!("!! {err}")),
+    }
+    result
+}
+
+// `eval` once the depth has been entered and traced
+fn eval_loop(expr: &Expr, env: &mut Env) -> Result<Expr> {
+    let mut tail: Option<Rc<Expr>> = None;
+    let mut env = env.clone();
+    loop {
+        let expr = tail.as_deref().unwrap_or(expr);
+        let step = match expr {
+            // lookup symbol
+            Expr::Symbol(symbol) => {
+                return Ok(env
+                    .get(*symbol)
+                    .ok_or(Error::UnboundSymbol(symbol.to_string()))?)
+            }
+
+            // return the bool, number, string, vector, values, dict or promise
+            Expr::Bool(_)
+            | Expr::Int(_)
+            | Expr::BigInt(_)
+            | Expr::Ratio { .. }
+            | Expr::Number(_)
+            | Expr::String(_)
+            | Expr::Char(_)
+            | Expr::Vector(_)
+            | Expr::Values(_)
+            | Expr::Dict(_)
+            | Expr::Promise(_) => return Ok(expr.clone()),
+
+            // `()` is nil, which evaluates to itself rather than being a call
+            // with nothing to call
+            Expr::List(list) if list.is_empty() => return Ok(expr.clone()),
+
+            // evaluate each item in list and apply
+            Expr::List(list) => eval_list(list, &mut env)?,
+
+            // shouldn't be allowed
+            Expr::Func(_) | Expr::EnvFunc(_) | Expr::Lambda { .. } | Expr::Macro { .. } => {
+                return Err(Error::Reason("Cannot evaluate a function".to_owned()).into())
+            }
+        };
+        match step {
+            Step::Done(value) => return Ok(value),
+            Step::Tail(next, next_env) => {
+                tail = Some(next);
+                env = next_env;
+            }
+        }
+    }
+}
+
+// spare argument buffers for builtin calls; one is taken for each call being
+// evaluated and handed back empty, so nesting never needs more than its depth
+thread_local! {
+    static ARG_BUFFERS: RefCell<Vec<Vec<Expr>>> = const { RefCell::new(Vec::new()) };
+}
+
+fn eval_list(list: &[Expr], env: &mut Env) -> Result<Step> {
+    // get car and cdr
+    let (op, args) = list
+        .split_first()
+        .ok_or(Error::Reason("Expected non-empty list".to_owned()))?;
+
+    // special forms receive their arguments unevaluated
+    if let Some(result) = eval_special_form(op, args, env) {
+        return result;
+    }
+
+    // evaluate the operator
+    let written = op;
+    let op = eval(op, env)?;
+
+    // a macro rewrites the unevaluated call, and the rewrite runs in its place
+    if let Expr::Macro {
+        params,
+        body,
+        env: macro_env,
+    } = &op
+    {
+        let mut local = bind_params("Macro", params, args.to_vec(), macro_env.clone())?;
+        let expansion = eval(body, &mut local)?;
+        return Ok(Step::Tail(Rc::new(expansion), env.clone()));
+    }
+
+    // check that op is a function
+    if !is_callable(&op) {
+        return Err(Error::NotCallable(written.to_string()).into());
+    }
+
+    // builtins only borrow their arguments, so the buffer outlives the call
+    if let Expr::Func(_) | Expr::EnvFunc(_) = op {
+        let mut buffer = ARG_BUFFERS
+            .with(|pool| pool.borrow_mut().pop())
+            .unwrap_or_default();
+        for arg in args {
+            buffer.push(eval(arg, env)?);
+        }
+        let result = match op {
+            Expr::EnvFunc(func) => func(&buffer, env),
+            Expr::Func(func) => func(&buffer),
+            _ => unreachable!("only builtins borrow a buffer"),
+        };
+        buffer.clear();
+        ARG_BUFFERS.with(|pool| pool.borrow_mut().push(buffer));
+        return result.map(Step::Done);
+    }
+
+    // evaluate args
+    let args = args
+        .iter()
+        .map(|x| eval(x, env))
+        .collect::<Result<Vec<Expr>>>()?;
+
+    // apply
+    apply_step(op, args, env)
+}
+
+// builtins and lambdas
+fn is_callable(expr: &Expr) -> bool {
+    matches!(expr, Expr::Func(_) | Expr::EnvFunc(_) | Expr::Lambda { .. })
+}
+
+// apply an evaluated operator to evaluated arguments, leaving a lambda's
+// body as a tail step for the caller; `env` is the caller's scope, which only
+// env-aware builtins see
+fn apply_step(op: Expr, args: Vec<Expr>, env: &mut Env) -> Result<Step> {
+    match &op {
+        Expr::Func(op) => op(&args).map(Step::Done),
+        Expr::EnvFunc(op) => op(&args, env).map(Step::Done),
+        Expr::Lambda {
+            params,
+            body,
+            env: closure_env,
+        } => {
+            let local = bind_params("Lambda", params, args, closure_env.clone())?;
+            Ok(Step::Tail(body.clone(), local))
+        }
+        op => Err(Error::NotCallable(op.to_string()).into()),
+    }
+}
+
+// bind params in a new scope on top of the captured environment; `kind`
+// names what is being called for the arity error
+fn bind_params(kind: &str, params: &Params, args: Vec<Expr>, captured: Rc<Env>) -> Result<Env> {
+    let min = params.required.len();
+    let max = params.rest.is_none().then_some(min + params.optional.len());
+    if args.len() < min || max.is_some_and(|max| args.len() > max) {
+        return Err(Error::ArityMismatch {
+            callee: kind.to_owned(),
+            min,
+            max,
+            got: args.len(),
+        }
+        .into());
+    }
+    let mut args = args.into_iter();
+    let mut data: HashMap<Symbol, Expr> =
+        params.required.iter().copied().zip(args.by_ref()).collect();
+    // defaults are evaluated at call time, in the scope the lambda was made in
+    for (name, default) in &params.optional {
+        let value = match args.next() {
+            Some(arg) => arg,
+            None => eval(default, &mut Env::clone(&captured))?,
+        };
+        data.insert(*name, value);
+    }
+    if let Some(rest) = params.rest {
+        data.insert(rest, Expr::List(Rc::new(args.collect())));
+    }
+    Ok(Env::with_parent(data, captured))
+}
+
+// apply any callable value, builtin or lambda, running it to completion
+fn apply(op: &Expr, args: Vec<Expr>, env: &mut Env) -> Result<Expr> {
+    match apply_step(op.clone(), args, env)? {
+        Step::Done(value) => Ok(value),
+        Step::Tail(body, mut env) => eval(&body, &mut env),
+    }
+}
+
+/// Names `eval` treats as special forms rather than function calls; keep in
+/// step with `eval_special_form`
+pub const SPECIAL_FORMS: &[&str] = &[
+    "and",
+    "begin",
+    "case",
+    "cond",
+    "cons-stream",
+    "define",
+    "defmacro",
+    "delay",
+    "do",
+    "dotimes",
+    "if",
+    "lambda",
+    "let",
+    "let*",
+    "let-values",
+    "load",
+    "or",
+    "quasiquote",
+    "quote",
+    "set!",
+    "time",
+    "try",
+    "unless",
+    "when",
+    "while",
+];
+
+// returns `None` when `op` does not name a special form
+fn eval_special_form(op: &Expr, args: &[Expr], env: &mut Env) -> Option<Result<Step>> {
+    let Expr::Symbol(symbol) = op else {
+        return None;
+    };
+    let result = match symbol.special_form()? {
+        "if" => eval_if(args, env),
+        "cond" => eval_cond(args, env),
+        "case" => eval_case(args, env),
+        "when" => eval_when(args, env, true),
+        "unless" => eval_when(args, env, false),
+        "while" => eval_while(args, env).map(Step::Done),
+        "dotimes" => eval_dotimes(args, env).map(Step::Done),
+        "define" => eval_define(args, env).map(Step::Done),
+        "defmacro" => eval_defmacro(args, env).map(Step::Done),
+        "set!" => eval_set(args, env).map(Step::Done),
+        "lambda" => eval_lambda(args, env).map(Step::Done),
+        "let" => eval_let(args, env),
+        "let*" => eval_let_star(args, env),
+        "let-values" => eval_let_values(args, env),
+        "quote" => eval_quote(args).map(Step::Done),
+        "delay" => eval_delay(args, env).map(Step::Done),
+        "cons-stream" => eval_cons_stream(args, env).map(Step::Done),
+        "quasiquote" => eval_quasiquote(args, env).map(Step::Done),
+        "load" => eval_load(args, env).map(Step::Done),
+        "time" => eval_time(args, env).map(Step::Done),
+        "try" => eval_try(args, env),
+        "and" => eval_and(args, env).map(Step::Done),
+        "begin" | "do" => eval_begin(args, env),
+        "or" => eval_or(args, env).map(Step::Done),
+        _ => return None,
+    };
+    Some(result)
+}
+
+// only `false` is falsey; every other value counts as true
+fn is_truthy(expr: &Expr) -> bool {
+    !matches!(expr, Expr::Bool(false))
+}
+
+fn eval_if(args: &[Expr], env: &mut Env) -> Result<Step> {
+    let [cond, then, otherwise] = args else {
+        return Err(Error::Reason("`if` expects (if cond then else)".to_owned()).into());
+    };
+    let branch = if is_truthy(&eval(cond, env)?) {
+        then
+    } else {
+        otherwise
+    };
+    Ok(Step::Tail(Rc::new(branch.clone()), env.clone()))
+}
+
+// `(cond (test body...)... (else body...))` runs the body of the first clause
+// whose test is truthy; with no matching clause, the `cond` evaluates to nil
+fn eval_cond(args: &[Expr], env: &mut Env) -> Result<Step> {
+    for clause in args {
+        let (test, body) = match clause {
+            Expr::List(parts) if parts.len() >= 2 => (&parts[0], &parts[1..]),
+            _ => return Err(Error::Reason(format!("Malformed `cond` clause `{clause}`")).into()),
+        };
+        let matched = match test {
+            Expr::Symbol(symbol) if symbol.as_str() == "else" => true,
+            _ => is_truthy(&eval(test, env)?),
+        };
+        if matched {
+            return eval_begin(body, env);
+        }
+    }
+    Ok(Step::Done(Expr::nil()))
+}
+
+// `(case expr (keys body...)... (else body...))` evaluates `expr` once and runs
+// the body of the first clause with a key `equal?` to it; `keys` is either one
+// datum or a list of them, and isn't evaluated
+fn eval_case(args: &[Expr], env: &mut Env) -> Result<Step> {
+    let Some((key, clauses)) = args.split_first() else {
+        return Err(Error::Reason("`case` expects (case expr clause...)".to_owned()).into());
+    };
+    let key = eval(key, env)?;
+    for clause in clauses {
+        let (keys, body) = match clause {
+            Expr::List(parts) if parts.len() >= 2 => (&parts[0], &parts[1..]),
+            _ => return Err(Error::Reason(format!("Malformed `case` clause `{clause}`")).into()),
+        };
+        let matched = match keys {
+            Expr::Symbol(symbol) if symbol.as_str() == "else" => true,
+            Expr::List(candidates) => candidates.contains(&key),
+            candidate => *candidate == key,
+        };
+        if matched {
+            return eval_begin(body, env);
+        }
+    }
+    Ok(Step::Done(Expr::nil()))
+}
+
+// `(when cond body...)` runs the body only if `cond` is truthy, and `unless`
+// only if it isn't; otherwise the result is nil and the body is never touched
+fn eval_when(args: &[Expr], env: &mut Env, run_if: bool) -> Result<Step> {
+    let Some((cond, body)) = args.split_first() else {
+        let form = if run_if { "when" } else { "unless" };
+        return Err(Error::Reason(format!("`{form}` expects ({form} cond body...)")).into());
+    };
+    if is_truthy(&eval(cond, env)?) == run_if {
+        eval_begin(body, env)
+    } else {
+        Ok(Step::Done(Expr::nil()))
+    }
+}
+
+// `(while cond body...)` runs the body for as long as `cond` is truthy, so the
+// body has to `set!` something for it to ever stop; the result is nil, even
+// when the body never runs. A loop that never stops can't be interrupted, not
+// even from the REPL, and the depth limit doesn't catch it since looping
+// doesn't nest evaluation; only an error ends it early
+fn eval_while(args: &[Expr], env: &mut Env) -> Result<Expr> {
+    let Some((cond, body)) = args.split_first() else {
+        return Err(Error::Reason("`while` expects (while cond body...)".to_owned()).into());
+    };
+    while is_truthy(&eval(cond, env)?) {
+        for expr in body {
+            eval(expr, env)?;
+        }
+    }
+    Ok(Expr::nil())
+}
+
+// `(dotimes (i n) body...)` runs the body `n` times, with `i` bound to 0, 1, ...
+// in a fresh scope each time; the result is nil
+fn eval_dotimes(args: &[Expr], env: &mut Env) -> Result<Expr> {
+    let malformed = || Error::Reason("`dotimes` expects (dotimes (name count) body...)".to_owned());
+    let Some((Expr::List(spec), body)) = args.split_first() else {
+        return Err(malformed().into());
+    };
+    let [Expr::Symbol(name), count] = &spec[..] else {
+        return Err(malformed().into());
+    };
+    let count = match eval(count, env)? {
+        Expr::Int(count) if count >= 0 => count,
+        count => {
+            return Err(Error::Reason(format!(
+                "`dotimes` expects a non-negative integer count, got `{count}`"
+            ))
+            .into())
+        }
+    };
+    let parent = Rc::new(env.clone());
+    for i in 0..count {
+        let mut local = Env::with_parent(HashMap::from([(*name, Expr::Int(i))]), parent.clone());
+        for expr in body {
+            eval(expr, &mut local)?;
+        }
+    }
+    Ok(Expr::nil())
+}
+
+// the first falsey value, or the last value; `(and)` is `true`
+fn eval_and(args: &[Expr], env: &mut Env) -> Result<Expr> {
+    let mut result = Expr::Bool(true);
+    for arg in args {
+        result = eval(arg, env)?;
+        if !is_truthy(&result) {
+            break;
+        }
+    }
+    Ok(result)
+}
+
+// the first truthy value, or the last value; `(or)` is `false`
+fn eval_or(args: &[Expr], env: &mut Env) -> Result<Expr> {
+    let mut result = Expr::Bool(false);
+    for arg in args {
+        result = eval(arg, env)?;
+        if is_truthy(&result) {
+            break;
+        }
+    }
+    Ok(result)
+}
+
+fn eval_begin(args: &[Expr], env: &mut Env) -> Result<Step> {
+    let Some((last, init)) = args.split_last() else {
+        return Ok(Step::Done(Expr::nil()));
+    };
+    for expr in init {
+        eval(expr, env)?;
+    }
+    Ok(Step::Tail(Rc::new(last.clone()), env.clone()))
+}
+
+fn eval_define(args: &[Expr], env: &mut Env) -> Result<Expr> {
+    let [Expr::Symbol(name), value] = args else {
+        return Err(Error::Reason("`define` expects (define name value)".to_owned()).into());
+    };
+    let value = eval(value, env)?;
+    env.insert(*name, value.clone());
+    Ok(value)
+}
+
+// unlike `define`, `set!` never creates a binding: it changes the one the name
+// already refers to, even if that lives in an enclosing scope
+fn eval_set(args: &[Expr], env: &mut Env) -> Result<Expr> {
+    let [Expr::Symbol(name), value] = args else {
+        return Err(Error::Reason("`set!` expects (set! name value)".to_owned()).into());
+    };
+    let value = eval(value, env)?;
+    if env.set(*name, value.clone()).is_err() {
+        return Err(Error::UnboundSymbol(name.to_string()).into());
+    }
+    Ok(value)
+}
+
+fn eval_lambda(args: &[Expr], env: &mut Env) -> Result<Expr> {
+    let [Expr::List(params), body] = args else {
+        return Err(Error::Reason("`lambda` expects (lambda (params...) body)".to_owned()).into());
+    };
+    Ok(Expr::Lambda {
+        params: parse_params("Lambda", params)?,
+        body: Rc::new(body.clone()),
+        env: Rc::new(env.clone()),
+    })
+}
+
+// `(defmacro name (params...) body)` binds `name` in the current scope, like
+// `define`
+fn eval_defmacro(args: &[Expr], env: &mut Env) -> Result<Expr> {
+    let [Expr::Symbol(name), Expr::List(params), body] = args else {
+        return Err(Error::Reason(
+            "`defmacro` expects (defmacro name (params...) body)".to_owned(),
+        )
+        .into());
+    };
+    let mac = Expr::Macro {
+        params: parse_params("Macro", params)?,
+        body: Rc::new(body.clone()),
+        env: Rc::new(env.clone()),
+    };
+    env.insert(*name, mac.clone());
+    Ok(mac)
+}
+
+// a `(name default)` pair is optional, and a trailing `. name` collects the
+// remaining arguments
+fn parse_params(kind: &str, params: &[Expr]) -> Result<Rc<Params>> {
+    let dot = params
+        .iter()
+        .position(|param| matches!(param, Expr::Symbol(s) if s.as_str() == "."));
+    let (required, rest) = match dot {
+        Some(i) => match &params[i + 1..] {
+            [Expr::Symbol(rest)] => (&params[..i], Some(*rest)),
+            _ => {
+                return Err(Error::Reason(format!(
+                    "{kind} rest param must be a single symbol after `.`"
+                ))
+                .into())
+            }
+        },
+        None => (params, None),
+    };
+    let mut parsed = Params {
+        required: vec![],
+        optional: vec![],
+        rest,
+    };
+    for param in required {
+        match param {
+            Expr::Symbol(name) if parsed.optional.is_empty() => parsed.required.push(*name),
+            Expr::Symbol(_) => {
+                return Err(Error::Reason(format!(
+                    "{kind} params without defaults must come first"
+                ))
+                .into())
+            }
+            Expr::List(_) => {
+                let (name, default) = parse_binding(param)?;
+                parsed.optional.push((name, default.clone()));
+            }
+            _ => return Err(Error::Reason(format!("{kind} params must be symbols")).into()),
+        }
+    }
+    Ok(Rc::new(parsed))
+}
+
+// split a `(name value)` binding into its parts
+fn parse_binding(binding: &Expr) -> Result<(Symbol, &Expr)> {
+    match binding {
+        Expr::List(pair) => match pair.as_slice() {
+            [Expr::Symbol(name), value] => Ok((*name, value)),
+            _ => Err(Error::Reason(format!("Malformed binding `{binding}`")).into()),
+        },
+        _ => Err(Error::Reason(format!("Malformed binding `{binding}`")).into()),
+    }
+}
+
+fn eval_let(args: &[Expr], env: &mut Env) -> Result<Step> {
+    let [Expr::List(bindings), body] = args else {
+        return Err(Error::Reason("`let` expects (let ((name value)...) body)".to_owned()).into());
+    };
+    // every value is evaluated in the outer env, so bindings can't see each other
+    let mut data = HashMap::new();
+    for binding in bindings.iter() {
+        let Expr::List(pair) = binding else {
+            return Err(Error::Reason(format!("Malformed binding `{binding}`")).into());
+        };
+        let [pattern, value] = pair.as_slice() else {
+            return Err(Error::Reason(format!("Malformed binding `{binding}`")).into());
+        };
+        destructure(pattern, eval(value, env)?, &mut data)?;
+    }
+    let local = Env::with_parent(data, Rc::new(env.clone()));
+    Ok(Step::Tail(Rc::new(body.clone()), local))
+}
+
+// bind the names in a `let` pattern: a symbol takes the whole value, while a
+// list pattern like `(a (b c) . rest)` takes a list apart element by element
+fn destructure(pattern: &Expr, value: Expr, data: &mut HashMap<Symbol, Expr>) -> Result<()> {
+    with_stack(|| {
+        let patterns = match pattern {
+            Expr::Symbol(name) => {
+                data.insert(*name, value);
+                return Ok(());
+            }
+            Expr::List(patterns) => patterns,
+            _ => return Err(Error::Reason(format!("Malformed pattern `{pattern}`")).into()),
+        };
+        let dot = patterns
+            .iter()
+            .position(|p| matches!(p, Expr::Symbol(s) if s.as_str() == "."));
+        let (fixed, rest) = match dot {
+            Some(i) => match &patterns[i + 1..] {
+                [rest] => (&patterns[..i], Some(rest)),
+                _ => {
+                    return Err(Error::Reason(format!(
+                        "Rest pattern in `{pattern}` must be a single pattern after `.`"
+                    ))
+                    .into())
+                }
+            },
+            None => (&patterns[..], None),
+        };
+        let items = match &value {
+            Expr::List(items)
+                if items.len() == fixed.len() || (rest.is_some() && items.len() > fixed.len()) =>
+            {
+                items
+            }
+            _ => {
+                return Err(Error::Reason(format!(
+                    "`{value}` doesn't match the pattern `{pattern}`"
+                ))
+                .into())
+            }
+        };
+        for (pattern, item) in fixed.iter().zip(items.iter()) {
+            destructure(pattern, item.clone(), data)?;
+        }
+        if let Some(rest) = rest {
+            let remaining = Expr::List(Rc::new(items[fixed.len()..].to_vec()));
+            destructure(rest, remaining, data)?;
+        }
+        Ok(())
+    })
+}
+
+// `(let-values (((name...) expr)...) body)` is `let` for expressions giving
+// several `values`, binding one name to each; a plain value counts as one
+fn eval_let_values(args: &[Expr], env: &mut Env) -> Result<Step> {
+    let [Expr::List(bindings), body] = args else {
+        return Err(Error::Reason(
+            "`let-values` expects (let-values (((name...) value)...) body)".to_owned(),
+        )
+        .into());
+    };
+    let mut data = HashMap::new();
+    for binding in bindings.iter() {
+        let malformed = || Error::Reason(format!("Malformed binding `{binding}`"));
+        let Expr::List(pair) = binding else {
+            return Err(malformed().into());
+        };
+        let [Expr::List(names), value] = pair.as_slice() else {
+            return Err(malformed().into());
+        };
+        let values = match eval(value, env)? {
+            Expr::Values(ref values) => values.clone(),
+            value => Rc::new(vec![value]),
+        };
+        if names.len() != values.len() {
+            let noun = if names.len() == 1 { "value" } else { "values" };
+            return Err(Error::Reason(format!(
+                "`let-values` expected {} {noun} for `{}`, got {}",
+                names.len(),
+                Expr::List(names.clone()),
+                values.len()
+            ))
+            .into());
+        }
+        for (name, value) in names.iter().zip(values.iter()) {
+            let Expr::Symbol(name) = name else {
+                return Err(malformed().into());
+            };
+            data.insert(*name, value.clone());
+        }
+    }
+    let local = Env::with_parent(data, Rc::new(env.clone()));
+    Ok(Step::Tail(Rc::new(body.clone()), local))
+}
+
+// like `let`, but each value is evaluated in the new scope once the bindings
+// before it are in place, so `(let* ((x 1) (y x)) y)` is `1`
+fn eval_let_star(args: &[Expr], env: &mut Env) -> Result<Step> {
+    let [Expr::List(bindings), body] = args else {
+        return Err(
+            Error::Reason("`let*` expects (let* ((name value)...) body)".to_owned()).into(),
+        );
+    };
+    let mut local = Env::with_parent(HashMap::new(), Rc::new(env.clone()));
+    for binding in bindings.iter() {
+        let (name, value) = parse_binding(binding)?;
+        let value = eval(value, &mut local)?;
+        local.insert(name, value);
+    }
+    Ok(Step::Tail(Rc::new(body.clone()), local))
+}
+
+// evaluate a file's forms into the current environment, returning the value
+// of the last one (an empty list for an empty file)
+fn eval_load(args: &[Expr], env: &mut Env) -> Result<Expr> {
+    let [path] = args else {
+        return Err(Error::Reason("`load` expects a single path".to_owned()).into());
+    };
+    let path = eval(path, env)?;
+    let Expr::String(path) = &path else {
+        return Err(Error::Reason("`load` expects the path as a string".to_owned()).into());
+    };
+    let source = fs::read_to_string(path).with_context(|| format!("Could not read `{path}`"))?;
+    let last = eval_file_source(&source, env).with_context(|| format!("Error in `{path}`"))?;
+    Ok(last.unwrap_or(Expr::nil()))
+}
+
+// evaluate the one argument, reporting how long that took to the env's output
+fn eval_time(args: &[Expr], env: &mut Env) -> Result<Expr> {
+    let [timed] = args else {
+        return Err(Error::Reason("`time` expects exactly one argument".to_owned()).into());
+    };
+    let start = Instant::now();
+    let value = eval(timed, env)?;
+    env.write_output(&format!("Elapsed time: {:.3?}\n", start.elapsed()))?;
+    Ok(value)
+}
+
+// `(try body... (catch e handler...))` is the value of the body, unless it
+// fails; then the handler runs instead, with the error message bound to `e`
+fn eval_try(args: &[Expr], env: &mut Env) -> Result<Step> {
+    let malformed =
+        || Error::Reason("`try` expects (try body... (catch name handler...))".to_owned());
+    let Some((Expr::List(catch), body)) = args.split_last() else {
+        return Err(malformed().into());
+    };
+    let [Expr::Symbol(keyword), Expr::Symbol(name), handler @ ..] = &catch[..] else {
+        return Err(malformed().into());
+    };
+    if keyword.as_str() != "catch" {
+        return Err(malformed().into());
+    }
+    match body.iter().try_fold(Expr::nil(), |_, expr| eval(expr, env)) {
+        Ok(value) => Ok(Step::Done(value)),
+        Err(err) => {
+            let message = Expr::String(format!("{err:#}"));
+            let mut local =
+                Env::with_parent(HashMap::from([(*name, message)]), Rc::new(env.clone()));
+            eval_begin(handler, &mut local)
+        }
+    }
+}
+
+fn eval_quote(args: &[Expr]) -> Result<Expr> {
+    let [quoted] = args else {
+        return Err(Error::Reason("`quote` expects exactly one argument".to_owned()).into());
+    };
+    Ok(quoted.clone())
+}
+
+fn eval_delay(args: &[Expr], env: &mut Env) -> Result<Expr> {
+    let [delayed] = args else {
+        return Err(Error::Reason("`delay` expects exactly one argument".to_owned()).into());
+    };
+    let promise = Promise::Delayed(Rc::new(delayed.clone()), env.clone());
+    Ok(Expr::Promise(Rc::new(RefCell::new(promise))))
+}
+
+// `(cons-stream head tail)` is `(list head (delay tail))`, a pair whose rest
+// is only worked out when `stream-cdr` asks for it
+fn eval_cons_stream(args: &[Expr], env: &mut Env) -> Result<Expr> {
+    let [head, tail] = args else {
+        return Err(Error::Reason("`cons-stream` expects a head and a tail".to_owned()).into());
+    };
+    let head = eval(head, env)?;
+    let tail = eval_delay(std::slice::from_ref(tail), env)?;
+    Ok(Expr::List(Rc::new(vec![head, tail])))
+}
+
+fn eval_quasiquote(args: &[Expr], env: &mut Env) -> Result<Expr> {
+    let [template] = args else {
+        return Err(Error::Reason("`quasiquote` expects exactly one argument".to_owned()).into());
+    };
+    expand_quasiquote(template, env, 1)
+}
+
+// `(name expr)`, i.e. one of the quoting forms, as `expr`
+fn quoting_form<'a>(name: &str, expr: &'a Expr) -> Option<&'a Expr> {
+    match expr {
+        Expr::List(list) => match list.as_slice() {
+            [Expr::Symbol(symbol), inner] if symbol.as_str() == name => Some(inner),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// copy `template`, evaluating what is unquoted; `depth` counts the enclosing
+// quasiquotes, and only an unquote that brings it to zero is evaluated, so a
+// nested template keeps its own unquotes for when it is expanded in turn
+fn expand_quasiquote(template: &Expr, env: &mut Env, depth: usize) -> Result<Expr> {
+    with_stack(|| {
+        let Expr::List(items) = template else {
+            return Ok(template.clone());
+        };
+        let nest = |name: &str, inner: Expr| {
+            Expr::List(Rc::new(vec![Expr::Symbol(Symbol::new(name)), inner]))
+        };
+        if let Some(inner) = quoting_form("unquote", template) {
+            return match depth {
+                1 => eval(inner, env),
+                _ => Ok(nest("unquote", expand_quasiquote(inner, env, depth - 1)?)),
+            };
+        }
+        if let Some(inner) = quoting_form("quasiquote", template) {
+            return Ok(nest(
+                "quasiquote",
+                expand_quasiquote(inner, env, depth + 1)?,
+            ));
+        }
+        let mut expanded = Vec::with_capacity(items.len());
+        for item in items.iter() {
+            match quoting_form("unquote-splicing", item) {
+                // `,@list` splices the items of `list` in
+                Some(inner) if depth == 1 => match &eval(inner, env)? {
+                    Expr::List(list) => expanded.extend_from_slice(list),
+                    value => {
+                        return Err(
+                            Error::Reason(format!("Cannot splice `{value}`, not a list")).into(),
+                        )
+                    }
+                },
+                Some(inner) => expanded.push(nest(
+                    "unquote-splicing",
+                    expand_quasiquote(inner, env, depth - 1)?,
+                )),
+                None => expanded.push(expand_quasiquote(item, env, depth)?),
+            }
+        }
+        Ok(Expr::List(Rc::new(expanded)))
+    })
+}
+
+/// Tokenize, parse and evaluate the single expression in `input`
+///
+/// Anything after the first expression is an error; use `eval_program` for
+/// input holding several top-level forms
+pub fn eval_str(input: &str, env: &mut Env) -> Result<Expr> {
+    let parsed = read_str(input)?;
+    let expr = eval(&parsed, env)?;
+    Ok(expr)
+}
+
+// tokenize and parse the single expression in `input`, without evaluating it
+fn read_str(input: &str) -> Result<Expr> {
+    let tokens = tokenize(input.to_owned())?;
+    let (parsed, rest) = parse(&tokens)?;
+    if let Some(token) = rest.first() {
+        return Err(token.error(format!("Unexpected trailing `{}`", token.text)));
+    }
+    Ok(parsed)
+}
+
+// parse every top-level form in `tokens`
+fn parse_all(tokens: &[Token]) -> Result<Vec<Expr>> {
+    let mut exprs = vec![];
+    let mut rest = tokens;
+    while !rest.is_empty() {
+        let (expr, new_rest) = parse(rest)?;
+        exprs.push(expr);
+        rest = new_rest;
+    }
+    Ok(exprs)
+}
+
+/// Evaluate every top-level form in `input` in order, returning the last value
+pub fn eval_program(input: &str, env: &mut Env) -> Result<Expr> {
+    let exprs = parse_all(&tokenize(input.to_owned())?)?;
+    let (last, init) = exprs
+        .split_last()
+        .ok_or_else(|| empty_input_error("Expected at least one expression"))?;
+    for expr in init {
+        eval(expr, env)?;
+    }
+    eval(last, env)
+}
+
+/// Evaluate every form in the file at `path` in a fresh environment, then
+/// print the value of the last one
+pub fn run_file(path: &Path) -> Result<()> {
+    let source =
+        fs::read_to_string(path).with_context(|| format!("Could not read `{}`", path.display()))?;
+    let mut env = default_env();
+    let last = eval_file_source(&source, &mut env)
+        .with_context(|| format!("Error in `{}`", path.display()))?;
+    if let Some(last) = last {
+        env.write_output(&format!("{}\n", last))?;
+    }
+    Ok(())
+}
+
+// like `eval_program`, but allows an empty file and reports which form failed
+fn eval_file_source(source: &str, env: &mut Env) -> Result<Option<Expr>> {
+    let tokens = tokenize(source.to_owned())?;
+    let mut rest = tokens.as_slice();
+    let mut last = None;
+    while let Some(start) = rest.first() {
+        let (expr, new_rest) = parse(rest)?;
+        let value = eval(&expr, env)
+            .with_context(|| format!("In the form at line:{} col:{}", start.line, start.col))?;
+        last = Some(value);
+        rest = new_rest;
+    }
+    Ok(last)
+}
+
+// `name` in the user's home directory
+fn home_path(name: &str) -> Option<PathBuf> {
+    let home = std::env::var_os("HOME")?;
+    Some(PathBuf::from(home).join(name))
+}
+
+// where REPL history is kept between sessions
+fn history_path() -> Option<PathBuf> {
+    home_path(".largo_history")
+}
+
+// evaluate the REPL's startup file into `env`; a missing file is fine, and
+// errors are reported to the env's output without stopping the session from
+// starting
+fn load_init_file(path: &Path, env: &mut Env) -> io::Result<()> {
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => {
+            return env.write_output(&format!("Could not read `{}`: {}\n", path.display(), err))
+        }
+    };
+    match eval_file_source(&source, env) {
+        Ok(_) => Ok(()),
+        Err(err) => env.write_output(&format!("Error in `{}`: {:#}\n", path.display(), err)),
+    }
+}
+
+// true when `input` only fails to parse because some parens are still open
+fn is_incomplete(input: &str) -> bool {
+    let Ok(tokens) = tokenize(input.to_owned()) else {
+        return false;
+    };
+    match parse_all(&tokens) {
+        Err(err) => matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::UnbalancedParens(..))
+        ),
+        Ok(_) => false,
+    }
+}
+
+// list every binding visible from `env`, innermost scope first
+fn describe_env(env: &Env) -> String {
+    let mut lines = Vec::new();
+    let mut scope = Some(env);
+    while let Some(current) = scope {
+        if !lines.is_empty() {
+            lines.push("; enclosing scope".to_owned());
+        }
+        let data = current.data.borrow();
+        let mut names: Vec<Symbol> = data.keys().copied().collect();
+        names.sort_by_key(|name| name.as_str());
+        for name in names {
+            let value = match &data[&name] {
+                Expr::Func(_) | Expr::EnvFunc(_) | Expr::Lambda { .. } => "<function>".to_owned(),
+                Expr::Macro { .. } => "<macro>".to_owned(),
+                value => value.to_string(),
+            };
+            lines.push(format!("{} = {}", name, value));
+        }
+        scope = current.parent.as_deref();
+    }
+    lines.join("\n")
+}
+
+// the special forms and the builtins of a fresh environment
+fn help_text() -> String {
+    let mut builtins: Vec<&str> = default_env()
+        .data
+        .borrow()
+        .keys()
+        .map(|s| s.as_str())
+        .collect();
+    builtins.sort();
+    format!(
+        "Special forms:\n  {}\nBuiltins:\n  {}",
+        SPECIAL_FORMS.join(" "),
+        builtins.join(" ")
+    )
+}
+
+// writes wherever an env's output goes, even if it is changed later, so
+// `:trace on` reports to the same place the session echoes to
+struct EnvOutput(Rc<RefCell<Box<dyn Write>>>);
+
+impl Write for EnvOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.borrow_mut().flush()
+    }
+}
+
+// run a `:command`, if `line` is one; these live outside the language so
+// they never shadow a symbol
+fn repl_command(line: &str, env: &Env) -> Option<Result<String>> {
+    let output = match line {
+        ":env" => describe_env(env),
+        ":help" => help_text(),
+        ":trace on" => {
+            env.set_trace(Some(Box::new(EnvOutput(env.output.clone()))));
+            "Tracing on".to_owned()
+        }
+        ":trace off" => {
+            env.set_trace(None);
+            "Tracing off".to_owned()
+        }
+        _ if line.starts_with(':') => {
+            return Some(Err(Error::Reason(format!(
+                "Unknown REPL command `{}`",
+                line
+            ))
+            .into()))
+        }
+        _ => return None,
+    };
+    Some(Ok(output))
+}
+
+// evaluate one line of REPL input, which may hold several forms, into the
+// text to echo back: the value of the last form, or nothing for a line that
+// is only comments
+fn eval_line(line: &str, env: &mut Env) -> Result<Option<String>> {
+    if let Some(output) = repl_command(line, env) {
+        return output.map(Some);
+    }
+    if tokenize(line.to_owned())?.is_empty() {
+        return Ok(None);
+    }
+    let expr = eval_program(line, env)?;
+    Ok(Some(expr.to_string()))
+}
+
+// how the REPL reports a failed line, with the causes that `load` and others
+// wrap around the underlying error
+fn error_report(err: &anyhow::Error) -> String {
+    format!("Error: {:#}", err)
+}
+
+/// One read from a `LineReader`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReadLine {
+    /// A line of input, without its line ending
+    Line(String),
+    /// The user asked to abandon the current input (Ctrl-C)
+    Interrupted,
+    /// There is no more input (Ctrl-D, or the end of a file)
+    Eof,
+}
+
+/// Where `run_repl_with` gets its input from
+pub trait LineReader {
+    /// Read one line, showing `prompt` first; sources that don't draw their
+    /// own prompt write it to `out`
+    fn read_line(&mut self, prompt: &str, out: &mut dyn Write) -> Result<ReadLine>;
+
+    /// Remember a complete entry, for sources that keep a history
+    fn add_history(&mut self, _entry: &str) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads lines from any buffered reader, such as a pipe or a byte slice
+pub struct PlainReader<R>(pub R);
+
+impl<R: BufRead> LineReader for PlainReader<R> {
+    fn read_line(&mut self, prompt: &str, out: &mut dyn Write) -> Result<ReadLine> {
+        out.write_all(prompt.as_bytes())?;
+        out.flush()?;
+        let mut line = String::new();
+        if self.0.read_line(&mut line)? == 0 {
+            return Ok(ReadLine::Eof);
+        }
+        let len = line.trim_end_matches(['\n', '\r']).len();
+        line.truncate(len);
+        Ok(ReadLine::Line(line))
+    }
+}
+
+// an interactive terminal with line editing, and history kept between sessions
+struct Terminal {
+    editor: DefaultEditor,
+    history: Option<PathBuf>,
+}
+
+impl Terminal {
+    fn new() -> Result<Terminal> {
+        let mut editor = DefaultEditor::new()?;
+        let history = history_path();
+        if let Some(path) = &history {
+            // there is no history yet on the first run
+            let _ = editor.load_history(path);
+        }
+        Ok(Terminal { editor, history })
+    }
+
+    fn save_history(&mut self) {
+        if let Some(path) = &self.history {
+            if let Err(err) = self.editor.save_history(path) {
+                eprintln!("Could not save history to `{}`: {}", path.display(), err);
+            }
+        }
+    }
+}
+
+impl LineReader for Terminal {
+    fn read_line(&mut self, prompt: &str, _out: &mut dyn Write) -> Result<ReadLine> {
+        match self.editor.readline(prompt) {
+            Ok(line) => Ok(ReadLine::Line(line)),
+            Err(ReadlineError::Interrupted) => Ok(ReadLine::Interrupted),
+            Err(ReadlineError::Eof) => Ok(ReadLine::Eof),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn add_history(&mut self, entry: &str) -> Result<()> {
+        self.editor.add_history_entry(entry)?;
+        Ok(())
+    }
+}
+
+/// How a REPL session looks
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplConfig {
+    /// Shown when a new expression is expected
+    pub prompt: String,
+    /// Shown while an expression's parens are still open
+    pub continuation_prompt: String,
+    /// Written once at the start of the session, if set
+    pub banner: Option<String>,
+    /// Whether the value of each expression is written back
+    pub echo: bool,
+    /// Evaluated before the first prompt, if it exists; `~/.largorc` by
+    /// default
+    pub init_file: Option<PathBuf>,
+}
+
+impl Default for ReplConfig {
+    fn default() -> ReplConfig {
+        ReplConfig {
+            prompt: ">>> ".to_owned(),
+            continuation_prompt: "... ".to_owned(),
+            banner: Some("~~~~ Largo ~~~~".to_owned()),
+            echo: true,
+            init_file: home_path(".largorc"),
+        }
+    }
+}
+
+/// Start an interactive session on stdin/stdout with the default
+/// `ReplConfig`
+///
+/// A line may hold several expressions; they are evaluated in order and the
+/// value of the last one is echoed
+pub fn run_repl() -> Result<()> {
+    let mut terminal = Terminal::new()?;
+    let result = run_repl_with(
+        &ReplConfig::default(),
+        &mut terminal,
+        Box::new(io::stdout()),
+    );
+    terminal.save_history();
+    result
+}
+
+/// Run a session reading from `input`, with results and anything the
+/// program prints written to `output`
+///
+/// Errors are reported there too, and the session ends at `quit` or when
+/// `input` runs out
+pub fn run_repl_with(
+    config: &ReplConfig,
+    input: &mut dyn LineReader,
+    output: Box<dyn Write>,
+) -> Result<()> {
+    let mut env = default_env();
+    env.set_output(output);
+    if let Some(banner) = &config.banner {
+        env.write_output(&format!("{}\n", banner))?;
+    }
+    if let Some(path) = &config.init_file {
+        load_init_file(path, &mut env)?;
+    }
+    // lines are collected until their parens balance
+    let mut pending = String::new();
+    loop {
+        let prompt = if pending.is_empty() {
+            &config.prompt
+        } else {
+            &config.continuation_prompt
+        };
+        let line = match input.read_line(prompt, &mut *env.output.borrow_mut())? {
+            ReadLine::Line(line) => line,
+            // Ctrl-C abandons the current input, Ctrl-D ends the session
+            ReadLine::Interrupted => {
+                pending.clear();
+                continue;
+            }
+            ReadLine::Eof => break,
+        };
+        let line = line.trim();
+        if pending.is_empty() {
+            if line.is_empty() {
+                continue;
+            }
+            if line == "quit" {
+                input.add_history(line)?;
+                break;
+            }
+        } else {
+            pending.push('\n');
+        }
+        pending.push_str(line);
+        if is_incomplete(&pending) {
+            continue;
+        }
+        input.add_history(&pending)?;
+        // a bad line is reported without ending the session
+        match eval_line(&pending, &mut env) {
+            Ok(Some(result)) if config.echo => env.write_output(&format!("{}\n", result))?,
+            Ok(_) => {}
+            Err(err) => env.write_output(&format!("{}\n", error_report(&err)))?,
+        }
+        pending.clear();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token_texts(input: &str) -> Vec<String> {
+        let tokens = tokenize(input.to_owned()).unwrap();
+        tokens.into_iter().map(|token| token.text).collect()
+    }
+
+    // whether `expr` is a float within rounding error of `expected`
+    fn approx_eq(expr: &Expr, expected: f64) -> bool {
+        matches!(expr, Expr::Number(n) if (n - expected).abs() < 1e-9)
+    }
+
+    #[test]
+    fn check_error() {
+        assert_eq!(
+            "Hello there".to_owned(),
+            format!("{}", Error::Reason("Hello there".to_owned()))
+        );
+    }
+
+    #[test]
+    fn check_tokenize() {
+        assert_eq!(
+            token_texts("(+ 1 2)"),
+            vec![
+                "(".to_owned(),
+                "+".to_owned(),
+                "1".to_owned(),
+                "2".to_owned(),
+                ")".to_owned()
+            ]
+        );
+        // any whitespace separates tokens, and decimals stay whole
+        let expected = ["(", "+", "1.5", "-2.25", ".5", ")"]
+            .map(str::to_owned)
+            .to_vec();
+        for input in [
+            "(+\t1.5\t-2.25\t.5)",
+            "(+\n1.5\n-2.25\n.5\n)",
+            "(+\r\n1.5\r\n-2.25\r\n.5)\r\n",
+            " \t(\r+ 1.5\u{a0}-2.25 \n\t .5 )",
+        ] {
+            assert_eq!(token_texts(input), expected, "{input:?}");
+        }
+        // a `\r` before a newline doesn't throw off the line count
+        let tokens = tokenize("(a\r\n\tb)".to_owned()).unwrap();
+        assert_eq!((tokens[2].line, tokens[2].col), (2, 2));
+        // `#(` is one token, but only at the start of one
+        assert_eq!(
+            token_texts("#(1 a#(2))"),
+            ["#(", "1", "a#", "(", "2", ")", ")"]
+                .map(str::to_owned)
+                .to_vec()
+        );
+        assert_eq!(
+            token_texts("{a{b}}"),
+            ["{", "a", "{", "b", "}", "}"].map(str::to_owned).to_vec()
+        );
+    }
+
+    #[test]
+    fn check_parse() {
+        let lexemes = "(+ 1 2)".to_owned();
+        let tokens = tokenize(lexemes).unwrap();
+        let (expr, rest) = parse(tokens.as_slice()).unwrap();
+        assert_eq!(
+            expr,
+            Expr::List(Rc::new(vec![
+                Expr::Symbol(Symbol::new("+")),
+                Expr::Int(1),
+                Expr::Int(2),
+            ]))
+        );
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn check_parse_atom() {
+        assert_eq!(parse_atom("1.0"), Expr::Number(1.0));
+        assert_eq!(parse_atom("1"), Expr::Int(1));
+        assert_eq!(parse_atom("Hello"), Expr::Symbol(Symbol::new("Hello")));
+        assert_eq!(parse_atom("hi1.0hi"), Expr::Symbol(Symbol::new("hi1.0hi")));
+        assert_eq!(parse_atom("true"), Expr::Bool(true));
+        assert_eq!(parse_atom("false"), Expr::Bool(false));
+        // `_` isn't a digit separator
+        for symbol in [
+            "1_000",
+            "1_",
+            "1__0",
+            "_1",
+            "-1_0",
+            "99999999999999999999_9",
+        ] {
+            assert_eq!(parse_atom(symbol), Expr::Symbol(Symbol::new(symbol)));
+        }
+        assert_eq!(
+            parse_atom("+99999999999999999999").to_string(),
+            "99999999999999999999"
+        );
+    }
+
+    #[test]
+    fn check_bool() {
+        let mut env = default_env();
+
+        assert_eq!(Expr::Bool(true).to_string(), "true");
+        assert_eq!(Expr::Bool(false).to_string(), "false");
+        assert_eq!(
+            eval(&Expr::Bool(false), &mut env).unwrap(),
+            Expr::Bool(false)
+        );
+    }
+
+    #[test]
+    fn check_default_env() {
+        let env = default_env();
+        let data = env.data.borrow();
+
+        let add = *match data.get(&Symbol::new("+")).unwrap() {
+            Expr::Func(f) => f,
+            _ => panic!("data did not return addition"),
+        };
+        let sub = *match data.get(&Symbol::new("-")).unwrap() {
+            Expr::Func(f) => f,
+            _ => panic!("data did not return subtraction"),
+        };
+
+        let exps = vec![Expr::Int(1), Expr::Int(2), Expr::Int(3)];
+
+        assert_eq!(add(&exps).unwrap(), Expr::Int(6));
+        assert_eq!(sub(&exps).unwrap(), Expr::Int(-4));
+    }
+
+    #[test]
+    fn check_eval() {
+        let mut env = default_env();
+
+        // Expr::List
+        let (exp1, _) = parse(&tokenize("(+ 1 2)".to_owned()).unwrap()).unwrap();
+        let (exp2, _) = parse(&tokenize("(+ 1 (+ 2 3 4))".to_owned()).unwrap()).unwrap();
+        let (exp3, _) = parse(&tokenize("(- 2 3)".to_owned()).unwrap()).unwrap();
+        let (exp4, _) = parse(&tokenize("(- 2 (+ 1 2 3))".to_owned()).unwrap()).unwrap();
+
+        assert_eq!(eval(&exp1, &mut env).unwrap(), Expr::Int(3));
+        assert_eq!(eval(&exp2, &mut env).unwrap(), Expr::Int(10));
+        assert_eq!(eval(&exp3, &mut env).unwrap(), Expr::Int(-1));
+        assert_eq!(eval(&exp4, &mut env).unwrap(), Expr::Int(-4));
+    }
+
+    #[test]
+    fn check_mul() {
+        let mut env = default_env();
+
+        let (exp1, _) = parse(&tokenize("(* 2 3 4)".to_owned()).unwrap()).unwrap();
+        let (exp2, _) = parse(&tokenize("(*)".to_owned()).unwrap()).unwrap();
+        let (exp3, _) = parse(&tokenize("(* 2 true)".to_owned()).unwrap()).unwrap();
+
+        assert_eq!(eval(&exp1, &mut env).unwrap(), Expr::Int(24));
+        assert_eq!(eval(&exp2, &mut env).unwrap(), Expr::Int(1));
+        assert!(eval(&exp3, &mut env).is_err());
+    }
+
+    #[test]
+    fn check_div() {
+        let mut env = default_env();
+
+        let (exp1, _) = parse(&tokenize("(/ 10 2)".to_owned()).unwrap()).unwrap();
+        let (exp2, _) = parse(&tokenize("(/ 100 2 5)".to_owned()).unwrap()).unwrap();
+        let (exp3, _) = parse(&tokenize("(/ 4)".to_owned()).unwrap()).unwrap();
+        let (exp4, _) = parse(&tokenize("(/ 1 0)".to_owned()).unwrap()).unwrap();
+        let (exp5, _) = parse(&tokenize("(/)".to_owned()).unwrap()).unwrap();
+
+        assert_eq!(eval(&exp1, &mut env).unwrap(), Expr::Int(5));
+        assert_eq!(eval(&exp2, &mut env).unwrap(), Expr::Int(10));
+        assert_eq!(
+            eval(&exp3, &mut env).unwrap(),
+            Expr::Ratio { num: 1, den: 4 }
+        );
+        assert_eq!(
+            eval(&exp4, &mut env).unwrap_err().to_string(),
+            "division by zero"
+        );
+        assert!(eval(&exp5, &mut env).is_err());
+    }
+
+    #[test]
+    fn check_ratio() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+        let ratio = |num, den| Expr::Ratio { num, den };
+
+        assert_eq!(run("3/4").unwrap(), ratio(3, 4));
+        assert_eq!(run("-6/8").unwrap(), ratio(-3, 4));
+        assert_eq!(run("4/2").unwrap(), Expr::Int(2));
+        for symbol in ["1/0", "3/-4", "1/", "/2", "a/b", "1/2/3"] {
+            assert_eq!(parse_atom(symbol), Expr::Symbol(Symbol::new(symbol)));
+        }
+
+        assert_eq!(run("(+ 1/3 1/6)").unwrap(), ratio(1, 2));
+        assert_eq!(run("(+ 1/2 1/2)").unwrap(), Expr::Int(1));
+        assert_eq!(run("(- 1/2 1)").unwrap(), ratio(-1, 2));
+        assert_eq!(run("(* 2/3 3/4)").unwrap(), ratio(1, 2));
+        assert_eq!(run("(/ 1/2 3)").unwrap(), ratio(1, 6));
+        assert_eq!(run("(/ 2 1/3)").unwrap(), Expr::Int(6));
+        assert_eq!(run("(mod 7/2 1)").unwrap(), ratio(1, 2));
+        assert_eq!(run("(mod -1/2 1)").unwrap(), ratio(1, 2));
+        assert_eq!(run("(abs -1/3)").unwrap(), ratio(1, 3));
+        // ints that don't divide evenly make a ratio too
+        assert_eq!(run("(/ 1 3)").unwrap(), ratio(1, 3));
+        assert_eq!(run("(/ 6 4)").unwrap(), ratio(3, 2));
+        assert_eq!(run("(/ 1/1 3)").unwrap(), ratio(1, 3));
+        assert_eq!(run("(/ 1 2 3)").unwrap(), ratio(1, 6));
+        assert_eq!(run("(/ 3)").unwrap(), ratio(1, 3));
+        assert_eq!(run("(/ -6 4)").unwrap(), ratio(-3, 2));
+        assert_eq!(run("(/ 6 -4)").unwrap(), ratio(-3, 2));
+        assert_eq!(
+            run("(/ (pow 10 20) (* 3 (pow 10 19)))").unwrap(),
+            ratio(10, 3)
+        );
+        // a float makes the whole result a float
+        assert_eq!(run("(+ 1/2 0.25)").unwrap(), Expr::Number(0.75));
+        assert_eq!(run("(* 1/4 2.0)").unwrap(), Expr::Number(0.5));
+
+        assert_eq!(run("(< 1/3 1/2 1)").unwrap(), Expr::Bool(true));
+        assert_eq!(run("(= 1/2 0.5)").unwrap(), Expr::Bool(true));
+        assert_eq!(run("(max 1/3 1/4)").unwrap(), ratio(1, 3));
+        assert_eq!(
+            run("(type-of 1/3)").unwrap(),
+            Expr::Symbol(Symbol::new("number"))
+        );
+        assert_eq!(
+            run("(number->string -1/3)").unwrap(),
+            Expr::String("-1/3".to_owned())
+        );
+        assert_eq!(run(r#"(string->number "2/6")"#).unwrap(), ratio(1, 3));
+        assert_eq!(ratio(5, 7).to_string(), "5/7");
+        // too big to stay exact
+        assert!(matches!(
+            run("(* 1/9223372036854775807 1/3)").unwrap(),
+            Expr::Number(_)
+        ));
+    }
+
+    #[test]
+    fn check_bigint() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+        let big = |digits: &str| Expr::BigInt(Rc::new(digits.parse().unwrap()));
+
+        assert_eq!(
+            run("(* 1000000000000 1000000000000)").unwrap(),
+            big("1000000000000000000000000")
+        );
+        run("(define fact (lambda (n) (if (< n 2) 1 (* n (fact (- n 1))))))").unwrap();
+        assert_eq!(
+            run("(fact 30)").unwrap(),
+            big("265252859812191058636308480000000")
+        );
+        assert_eq!(run("(/ (fact 30) (fact 28))").unwrap(), Expr::Int(870));
+        // results that fit are plain ints again
+        assert_eq!(
+            run("(- 9223372036854775808 1)").unwrap(),
+            Expr::Int(i64::MAX)
+        );
+        assert_eq!(
+            run("(+ -9223372036854775808 -1)").unwrap(),
+            big("-9223372036854775809")
+        );
+        assert_eq!(
+            run("(abs -9223372036854775808)").unwrap(),
+            big("9223372036854775808")
+        );
+        assert_eq!(run("(mod -100000000000000000000 7)").unwrap(), Expr::Int(5));
+        assert_eq!(
+            run("(pow 2 100)").unwrap(),
+            big("1267650600228229401496703205376")
+        );
+        assert_eq!(run("(pow 2 0)").unwrap(), Expr::Int(1));
+
+        // comparisons stay exact past where floats can tell numbers apart
+        assert_eq!(
+            run("(= (fact 25) (+ (fact 25) 1))").unwrap(),
+            Expr::Bool(false)
+        );
+        assert_eq!(
+            run("(< (fact 25) (+ (fact 25) 1))").unwrap(),
+            Expr::Bool(true)
+        );
+        assert_eq!(run("(< 1/2 (fact 25) 1e30)").unwrap(), Expr::Bool(true));
+        assert_eq!(
+            run("(max 1 (fact 21) 2.5)").unwrap(),
+            big("51090942171709440000")
+        );
+
+        // floats give floats, as does a quotient too big for a ratio
+        assert_eq!(
+            run("(/ (fact 25) 7.0)").unwrap(),
+            Expr::Number(15511210043330985984000000.0 / 7.0)
+        );
+        assert!(matches!(
+            run("(/ (fact 25) 23456789)").unwrap(),
+            Expr::Number(_)
+        ));
+        assert_eq!(
+            run("(type-of (fact 25))").unwrap(),
+            Expr::Symbol(Symbol::new("number"))
+        );
+        assert_eq!(
+            run("(fact 25)").unwrap().to_string(),
+            "15511210043330985984000000"
+        );
+        assert!(run("(bit-and (fact 25) 1)").is_err());
+    }
+
+    #[test]
+    fn check_comparisons() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        assert_eq!(run("(< 1 2 3)").unwrap(), Expr::Bool(true));
+        assert_eq!(run("(< 1 3 2)").unwrap(), Expr::Bool(false));
+        assert_eq!(run("(> 3 2 1)").unwrap(), Expr::Bool(true));
+        assert_eq!(run("(> 3 1 2)").unwrap(), Expr::Bool(false));
+        assert_eq!(run("(>= 3 3 1)").unwrap(), Expr::Bool(true));
+        assert_eq!(run("(<= 1 1 0)").unwrap(), Expr::Bool(false));
+        assert_eq!(run("(= 2 2 2)").unwrap(), Expr::Bool(true));
+        assert_eq!(run("(= 2 2 3)").unwrap(), Expr::Bool(false));
+        assert!(run("(< 1)").is_err());
+        assert!(run("(= true 1)").is_err());
+    }
+
+    #[test]
+    fn check_if() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        assert_eq!(run("(if true 1 2)").unwrap(), Expr::Int(1));
+        assert_eq!(run("(if false 1 2)").unwrap(), Expr::Int(2));
+        assert_eq!(run("(if (< 1 2) (+ 1 1) 0)").unwrap(), Expr::Int(2));
+        // anything other than `false` is truthy
+        assert_eq!(run("(if 0 1 2)").unwrap(), Expr::Int(1));
+        // the untaken branch is never evaluated
+        assert_eq!(run("(if true 1 (undefined-symbol))").unwrap(), Expr::Int(1));
+        assert_eq!(
+            run("(if false (undefined-symbol) 2)").unwrap(),
+            Expr::Int(2)
+        );
+        assert!(run("(if true 1)").is_err());
+    }
+
+    #[test]
+    fn check_define() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        assert_eq!(run("(define x 10)").unwrap(), Expr::Int(10));
+        assert_eq!(run("(+ x 5)").unwrap(), Expr::Int(15));
+        assert_eq!(run("(define x (* x 2))").unwrap(), Expr::Int(20));
+        assert_eq!(run("x").unwrap(), Expr::Int(20));
+        assert!(run("(define 1 2)").is_err());
+        assert!(run("(define y)").is_err());
+    }
+
+    #[test]
+    fn check_lambda() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        assert_eq!(run("((lambda (a b) (+ a b)) 1 2)").unwrap(), Expr::Int(3));
+        assert_eq!(
+            run("(define add (lambda (a b) (+ a b)))")
+                .unwrap()
+                .to_string(),
+            "Lambda"
+        );
+        assert_eq!(run("(add 3 4)").unwrap(), Expr::Int(7));
+        // params are local to the call
+        assert!(run("a").is_err());
+        assert_eq!(
+            run("(add 1)").unwrap_err().to_string(),
+            "Lambda expected 2 arguments, got 1"
+        );
+        assert!(run("(lambda (1) 1)").is_err());
+        assert!(run("(lambda x)").is_err());
+    }
+
+    #[test]
+    fn check_nested_env() {
+        let outer = Rc::new(default_env());
+        let mut outer_data = HashMap::new();
+        outer_data.insert(Symbol::new("x"), Expr::Int(1));
+        let outer = Rc::new(Env::with_parent(outer_data, outer));
+
+        let mut inner_data = HashMap::new();
+        inner_data.insert(Symbol::new("x"), Expr::Int(2));
+        let mut inner = Env::with_parent(inner_data, outer.clone());
+
+        // the inner binding shadows the outer one, builtins are still visible
+        assert_eq!(eval(&parse_atom("x"), &mut inner).unwrap(), Expr::Int(2));
+        assert!(inner.get(Symbol::new("+")).is_some());
+        drop(inner);
+
+        // once the inner scope is gone the outer value is back
+        assert_eq!(outer.get(Symbol::new("x")), Some(Expr::Int(1)));
+
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+        run("(define x 1)").unwrap();
+        assert_eq!(run("((lambda (x) (+ x 10)) 2)").unwrap(), Expr::Int(12));
+        assert_eq!(run("x").unwrap(), Expr::Int(1));
+    }
+
+    #[test]
+    fn check_let() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        assert_eq!(run("(let ((x 1) (y 2)) (+ x y))").unwrap(), Expr::Int(3));
+        // bindings are only visible inside the body
+        assert!(run("x").is_err());
+        // bindings don't see each other
+        assert!(run("(let ((x 1) (y x)) y)").is_err());
+        run("(define x 10)").unwrap();
+        assert_eq!(run("(let ((x 1) (y x)) y)").unwrap(), Expr::Int(10));
+        assert_eq!(run("x").unwrap(), Expr::Int(10));
+        // malformed bindings
+        assert!(run("(let ((x)) x)").is_err());
+        assert!(run("(let (x 1) x)").is_err());
+        assert!(run("(let ((1 2)) 1)").is_err());
+        assert!(run("(let ((x 1)))").is_err());
+    }
+
+    #[test]
+    fn check_string() {
+        assert_eq!(
+            token_texts(r#"(print "hello world" "(x)")"#),
+            vec![
+                "(".to_owned(),
+                "print".to_owned(),
+                r#""hello world""#.to_owned(),
+                r#""(x)""#.to_owned(),
+                ")".to_owned()
+            ]
+        );
+        assert_eq!(token_texts(r#""a \" b""#), vec![r#""a \" b""#.to_owned()]);
+        assert!(tokenize(r#"(print "oops)"#.to_owned()).is_err());
+
+        assert_eq!(
+            parse_atom(r#""hello world""#),
+            Expr::String("hello world".to_owned())
+        );
+        assert_eq!(parse_atom(r#""a\"b\n""#), Expr::String("a\"b\n".to_owned()));
+        assert_eq!(parse_atom(r#""""#), Expr::String("".to_owned()));
+        assert_eq!(Expr::String("hi".to_owned()).to_string(), r#""hi""#);
+        assert_eq!(Expr::String("a\"b".to_owned()).to_string(), r#""a\"b""#);
+
+        let mut env = default_env();
+        assert_eq!(
+            eval_str(r#""a b""#, &mut env).unwrap(),
+            Expr::String("a b".to_owned())
+        );
+    }
+
+    #[test]
+    fn check_comments() {
+        let expected: Vec<String> = vec!["(", "+", "1", "2", ")"]
+            .into_iter()
+            .map(|t| t.to_owned())
+            .collect();
+        assert_eq!(token_texts("(+ 1 2) ; this is three"), expected);
+        assert_eq!(
+            token_texts("; leading comment\n(+ 1 ; inline \"quote\n 2)"),
+            expected
+        );
+        // semicolons inside strings are kept
+        assert_eq!(token_texts(r#""a;b" ; c"#), vec![r#""a;b""#.to_owned()]);
+
+        assert_eq!(token_texts("(+ 1 #| two |# 2)"), expected);
+        assert_eq!(token_texts("#| a #| b |# c |#(+ 1 2)"), expected);
+        assert_eq!(token_texts("(+ 1\n#| (- 1\n  2) ; |#\n 2)"), expected);
+        // `|#` is not closed by the `|` of `#|`
+        assert_eq!(token_texts("#||#(+ 1 2)"), expected);
+        assert_eq!(
+            token_texts(r##""#| a |#""##),
+            vec![r##""#| a |#""##.to_owned()]
+        );
+        assert!(tokenize("(+ 1 #| 2)".to_owned()).is_err());
+        assert!(tokenize("#| a #| b |# c".to_owned()).is_err());
+        let tokens = tokenize("#|\n|# x".to_owned()).unwrap();
+        assert_eq!((tokens[0].line, tokens[0].col), (2, 4));
+    }
+
+    #[test]
+    fn check_quote() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        assert_eq!(run("'foo").unwrap(), Expr::Symbol(Symbol::new("foo")));
+        assert_eq!(
+            run("(quote foo)").unwrap(),
+            Expr::Symbol(Symbol::new("foo"))
+        );
+        assert_eq!(
+            run("'(1 2 3)").unwrap(),
+            Expr::List(Rc::new(vec![Expr::Int(1), Expr::Int(2), Expr::Int(3)]))
+        );
+        assert_eq!(
+            run("'(+ (a b) 'c)").unwrap(),
+            Expr::List(Rc::new(vec![
+                Expr::Symbol(Symbol::new("+")),
+                Expr::List(Rc::new(vec![
+                    Expr::Symbol(Symbol::new("a")),
+                    Expr::Symbol(Symbol::new("b"))
+                ])),
+                Expr::List(Rc::new(vec![
+                    Expr::Symbol(Symbol::new("quote")),
+                    Expr::Symbol(Symbol::new("c"))
+                ])),
+            ]))
+        );
+        assert!(run("(quote)").is_err());
+        assert!(run("'").is_err());
+    }
+
+    #[test]
+    fn check_list_builtins() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        assert_eq!(
+            run("(list 1 (+ 1 1) 3)").unwrap(),
+            Expr::List(Rc::new(vec![Expr::Int(1), Expr::Int(2), Expr::Int(3)]))
+        );
+        assert_eq!(run("(list)").unwrap(), Expr::nil());
+        assert_eq!(
+            run("(cons 1 (list 2))").unwrap(),
+            Expr::List(Rc::new(vec![Expr::Int(1), Expr::Int(2)]))
+        );
+        assert!(run("(cons 1 2)").is_err());
+        assert_eq!(run("(car (list 1 2 3))").unwrap(), Expr::Int(1));
+        assert_eq!(
+            run("(first '(a b))").unwrap(),
+            Expr::Symbol(Symbol::new("a"))
+        );
+        assert_eq!(
+            run("(cdr (list 1 2 3))").unwrap(),
+            Expr::List(Rc::new(vec![Expr::Int(2), Expr::Int(3)]))
+        );
+        assert_eq!(run("(rest (list 1))").unwrap(), Expr::nil());
+        assert_eq!(
+            run("(car (list))").unwrap_err().to_string(),
+            "`car` of an empty list"
+        );
+        assert!(run("(cdr (list))").is_err());
+        assert!(run("(car 1)").is_err());
+    }
+
+    #[test]
+    fn check_syntax_error_positions() {
+        let tokens = tokenize("(+ 1\n  \"two\" 3)".to_owned()).unwrap();
+        let positions: Vec<(u32, u32)> = tokens.iter().map(|t| (t.line, t.col)).collect();
+        assert_eq!(
+            positions,
+            vec![(1, 1), (1, 2), (1, 4), (2, 3), (2, 9), (2, 10)]
+        );
+
+        let mut env = default_env();
+        assert_eq!(
+            eval_str("\n  )", &mut env).unwrap_err().to_string(),
+            "Syntax error; line:2 col:3; Unexpected `)`"
+        );
+        let err = tokenize("(+ 1\n   \"abc".to_owned()).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::ParseError {
+                line: 2,
+                col: 4,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn check_unbalanced_parens() {
+        let mut env = default_env();
+
+        assert_eq!(
+            eval_str("(+ 1 2", &mut env).unwrap_err().to_string(),
+            "Parens not balanced; 1 parens needed to close `(` at line:1 col:1"
+        );
+        let err = eval_str("(define f\n  (lambda (x)", &mut env).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::UnbalancedParens(2, 2, 3))
+        ));
+        let err = eval_str("'(1 '(2 (3", &mut env).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::UnbalancedParens(3, _, _))
+        ));
+
+        // trailing input is an error instead of being dropped
+        assert_eq!(
+            eval_str("(+ 1 2))", &mut env).unwrap_err().to_string(),
+            "Syntax error; line:1 col:8; Unexpected trailing `)`"
+        );
+        assert!(eval_str("(+ 1 2) 3", &mut env).is_err());
+    }
+
+    #[test]
+    fn check_eval_line() {
+        let mut env = default_env();
+
+        assert_eq!(
+            eval_line("(define x 2)", &mut env).unwrap().as_deref(),
+            Some("2")
+        );
+        assert!(eval_line("(+ x undefined)", &mut env).is_err());
+        assert!(eval_line("(+ x", &mut env).is_err());
+        // the env is still usable after an error
+        assert_eq!(
+            eval_line("(+ x 1)", &mut env).unwrap().as_deref(),
+            Some("3")
+        );
+
+        // every form on the line runs, and the last value is echoed
+        assert_eq!(
+            eval_line("(define a 1) (define b 2) (+ a b)", &mut env)
+                .unwrap()
+                .as_deref(),
+            Some("3")
+        );
+        assert_eq!(eval_line("b", &mut env).unwrap().as_deref(), Some("2"));
+        // forms before an error have still run
+        assert!(eval_line("(define c 3) (car 1) (define d 4)", &mut env).is_err());
+        assert_eq!(eval_line("c", &mut env).unwrap().as_deref(), Some("3"));
+        assert!(eval_line("d", &mut env).is_err());
+        assert!(eval_line("(+ 1 2))", &mut env).is_err());
+        // a line of only comments has nothing to echo
+        assert_eq!(eval_line("; note", &mut env).unwrap(), None);
+        assert_eq!(eval_line("#| note |#", &mut env).unwrap(), None);
+        assert_eq!(
+            eval_line("c ; note", &mut env).unwrap().as_deref(),
+            Some("3")
+        );
+    }
+
+    #[test]
+    fn check_eval_program() {
+        let mut env = default_env();
+
+        assert_eq!(
+            eval_program("(define x 1) (define y 2)\n(+ x y)", &mut env).unwrap(),
+            Expr::Int(3)
+        );
+        assert_eq!(
+            eval_program("(+ 1 2) (+ 3 4)", &mut env).unwrap(),
+            Expr::Int(7)
+        );
+        assert!(eval_program("; only a comment", &mut env).is_err());
+        assert!(eval_program("(+ 1 2) (+ 3", &mut env).is_err());
+        // single-expression mode still rejects the extra form
+        assert!(eval_str("(+ 1 2) (+ 3 4)", &mut env).is_err());
+    }
+
+    #[test]
+    fn check_logic() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        assert_eq!(run("(and true 1 2)").unwrap(), Expr::Int(2));
+        assert_eq!(run("(and 1 false 2)").unwrap(), Expr::Bool(false));
+        assert_eq!(run("(and)").unwrap(), Expr::Bool(true));
+        assert_eq!(run("(or false 1 2)").unwrap(), Expr::Int(1));
+        assert_eq!(run("(or false false)").unwrap(), Expr::Bool(false));
+        assert_eq!(run("(or)").unwrap(), Expr::Bool(false));
+        // short-circuiting skips the remaining operands
+        assert_eq!(run("(and false (undefined))").unwrap(), Expr::Bool(false));
+        assert_eq!(run("(or 0 (undefined))").unwrap(), Expr::Int(0));
+        assert!(run("(and true (undefined))").is_err());
+
+        assert_eq!(run("(not false)").unwrap(), Expr::Bool(true));
+        assert_eq!(run("(not 0)").unwrap(), Expr::Bool(false));
+        assert!(run("(not)").is_err());
+    }
+
+    #[test]
+    fn check_int() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        assert_eq!(run("(+ 1 2)").unwrap(), Expr::Int(3));
+        assert_eq!(run("(+ 1.0 2)").unwrap(), Expr::Number(3.0));
+        assert_eq!(run("(* 2 3.5)").unwrap(), Expr::Number(7.0));
+        assert_eq!(run("(- 10 3 2)").unwrap(), Expr::Int(5));
+        assert_eq!(run("(- 10 3)").unwrap(), Expr::Int(7));
+        // a single operand is negated
+        assert_eq!(run("(- 5)").unwrap(), Expr::Int(-5));
+        assert_eq!(run("(- -2.5)").unwrap(), Expr::Number(2.5));
+        assert_eq!(run("(- 1/3)").unwrap(), Expr::Ratio { num: -1, den: 3 });
+        assert_eq!(
+            run("(- -9223372036854775808)").unwrap().to_string(),
+            "9223372036854775808"
+        );
+        assert!(run("(-)").is_err());
+        assert_eq!(run("(/ 10 2)").unwrap(), Expr::Int(5));
+        assert_eq!(run("(/ 10 4)").unwrap(), Expr::Ratio { num: 5, den: 2 });
+        assert_eq!(run("(< 1 1.5 2)").unwrap(), Expr::Bool(true));
+        assert_eq!(run("(= 1 1.0)").unwrap(), Expr::Bool(true));
+        // integers too large for i64 grow into bigints
+        assert_eq!(
+            run("(* 9223372036854775807 2)").unwrap().to_string(),
+            "18446744073709551614"
+        );
+
+        assert_eq!(run("(+ 1 2)").unwrap().to_string(), "3");
+        assert_eq!(run("(+ 1.0 2)").unwrap().to_string(), "3.0");
+        assert_eq!(run("(/ 10 4)").unwrap().to_string(), "5/2");
+        assert_eq!(run("(/ 10.0 4)").unwrap().to_string(), "2.5");
+    }
+
+    #[test]
+    fn check_display_round_trip() {
+        let sources = [
+            "(+ 1 2)",
+            "(define f (lambda (x y) (* x (- y 1.5))))",
+            "((a (b (c))) () \"str ing\" true -3)",
+        ];
+        for source in sources {
+            let (expr, _) = parse(&tokenize(source.to_owned()).unwrap()).unwrap();
+            let printed = expr.to_string();
+            assert_eq!(printed, source);
+            let (reparsed, _) = parse(&tokenize(printed).unwrap()).unwrap();
+            assert_eq!(reparsed, expr);
+        }
+    }
+
+    #[test]
+    fn check_begin() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        assert_eq!(
+            run("(begin (define x 1) (define y (+ x 1)) (* x y))").unwrap(),
+            Expr::Int(2)
+        );
+        assert_eq!(run("(do 1 2 3)").unwrap(), Expr::Int(3));
+        assert_eq!(run("y").unwrap(), Expr::Int(2));
+        assert_eq!(run("(begin)").unwrap(), Expr::nil());
+    }
+
+    #[test]
+    fn check_recursion() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        run("(define fact (lambda (n) (if (< n 2) 1 (* n (fact (- n 1))))))").unwrap();
+        assert_eq!(run("(fact 5)").unwrap(), Expr::Int(120));
+
+        // `odd?` doesn't exist yet when `even?` is created
+        run("(define even? (lambda (n) (if (= n 0) true (odd? (- n 1)))))").unwrap();
+        run("(define odd? (lambda (n) (if (= n 0) false (even? (- n 1)))))").unwrap();
+        assert_eq!(run("(even? 10)").unwrap(), Expr::Bool(true));
+        assert_eq!(run("(odd? 7)").unwrap(), Expr::Bool(true));
+        assert_eq!(run("(even? 7)").unwrap(), Expr::Bool(false));
+    }
+
+    #[test]
+    fn check_max_depth() {
+        // the default depth is reached well within a test thread's stack
+        let mut env = default_env();
+        eval_str("(define loop (lambda (n) (+ 1 (loop n))))", &mut env).unwrap();
+        assert_eq!(
+            eval_str("(loop 0)", &mut env).unwrap_err().to_string(),
+            "maximum recursion depth exceeded"
+        );
+        // the depth is released again after the error
+        assert_eq!(eval_str("(+ 1 2)", &mut env).unwrap(), Expr::Int(3));
+        // as is the stack, so this can go just as deep again
+        eval_str(
+            "(define count (lambda (n) (if (= n 0) 0 (+ 1 (count (- n 1))))))",
+            &mut env,
+        )
+        .unwrap();
+        assert_eq!(eval_str("(count 9000)", &mut env).unwrap(), Expr::Int(9000));
+
+        assert_eq!(eval_str("(count 50)", &mut env).unwrap(), Expr::Int(50));
+        env.set_max_depth(50);
+        assert!(eval_str("(count 50)", &mut env).is_err());
+    }
+
+    #[test]
+    fn check_deep_nesting() {
+        let mut env = default_env();
+        let n = 100_000;
+        let (open, close) = ("(".repeat(n), ")".repeat(n));
+
+        // reading, printing, comparing, quasiquoting and destructuring all follow the
+        // nesting down, past what a test thread's stack would hold
+        let nested = eval_str(&format!("'{open}x{close}"), &mut env).unwrap();
+        assert!(nested.to_string() == format!("{open}x{close}"));
+        assert_eq!(
+            eval_str(&format!("`{open}x{close}"), &mut env).unwrap(),
+            nested
+        );
+        assert_eq!(
+            eval_str(
+                &format!("(let (({open}a{close} '{open}1{close})) a)"),
+                &mut env
+            )
+            .unwrap(),
+            Expr::Int(1)
+        );
+        assert!(eval_str(
+            &format!("(let (({open}a{close} '{open}{close})) a)"),
+            &mut env
+        )
+        .is_err());
+
+        // evaluating it stops at the depth limit instead
+        assert!(eval_str(&format!("{open}x{close}"), &mut env).is_err());
+        assert!(matches!(
+            eval_str(&open, &mut env)
+                .unwrap_err()
+                .downcast_ref::<Error>(),
+            Some(Error::UnbalancedParens(..))
+        ));
+    }
+
+    #[test]
+    fn check_tail_calls() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        run("(define countdown (lambda (n) (if (= n 0) 'done (countdown (- n 1)))))").unwrap();
+        assert_eq!(
+            run("(countdown 1000000)").unwrap(),
+            Expr::Symbol(Symbol::new("done"))
+        );
+        run("(define sum (lambda (n acc) (let ((m (- n 1))) (if (< n 1) acc (begin 0 (sum m (+ acc n)))))))").unwrap();
+        assert_eq!(run("(sum 100000 0)").unwrap(), Expr::Int(5000050000));
+    }
+
+    #[test]
+    fn check_map() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        assert_eq!(
+            run("(map (lambda (x) (* x x)) (list 1 2 3))")
+                .unwrap()
+                .to_string(),
+            "(1 4 9)"
+        );
+        assert_eq!(
+            run("(map car '((1 2) (3 4)))").unwrap().to_string(),
+            "(1 3)"
+        );
+        assert_eq!(run("(map car (list))").unwrap(), Expr::nil());
+        assert_eq!(
+            run("(map car 1)").unwrap_err().to_string(),
+            "`map` expects a list as its second argument"
+        );
+        assert!(run("(map 1 (list 1))").is_err());
+        assert!(run("(map (lambda (a b) a) (list 1))").is_err());
+    }
+
+    #[test]
+    fn check_filter_fold() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        assert_eq!(
+            run("(filter (lambda (x) (> x 2)) (list 1 2 3 4))")
+                .unwrap()
+                .to_string(),
+            "(3 4)"
+        );
+        assert_eq!(
+            run("(filter (lambda (x) false) (list 1 2))").unwrap(),
+            Expr::nil()
+        );
+        assert!(run("(filter car 1)").is_err());
+
+        assert_eq!(run("(fold + 0 (list 1 2 3 4))").unwrap(), Expr::Int(10));
+        assert_eq!(run("(reduce + 0 (list))").unwrap(), Expr::Int(0));
+        // the accumulator comes first: ((0 - 1) - 2)
+        assert_eq!(run("(fold - 0 (list 1 2))").unwrap(), Expr::Int(-3));
+        assert_eq!(
+            run("(fold (lambda (acc x) (cons x acc)) (list) (list 1 2 3))")
+                .unwrap()
+                .to_string(),
+            "(3 2 1)"
+        );
+        assert!(run("(fold + 0 1)").is_err());
+    }
+
+    #[test]
+    fn check_apply() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        assert_eq!(run("(apply + (list 1 2 3))").unwrap(), Expr::Int(6));
+        assert_eq!(
+            run("(apply (lambda (a b) (- a b)) '(5 3))").unwrap(),
+            Expr::Int(2)
+        );
+        assert_eq!(run("(apply + (list))").unwrap(), Expr::Int(0));
+        assert_eq!(
+            run("(apply + 1)").unwrap_err().to_string(),
+            "`apply` expects a list of arguments, got `1`"
+        );
+        assert_eq!(
+            run("(apply 1 (list 1))").unwrap_err().to_string(),
+            "`1` is not a function"
+        );
+    }
+
+    #[test]
+    fn check_math() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        assert_eq!(run("(sqrt 9)").unwrap(), Expr::Number(3.0));
+        assert_eq!(
+            run("(sqrt -1)").unwrap_err().to_string(),
+            "`sqrt` of a negative number"
+        );
+        assert_eq!(run("(pow 2 10)").unwrap(), Expr::Int(1024));
+        assert_eq!(run("(pow 2 -1)").unwrap(), Expr::Number(0.5));
+        assert_eq!(run("(pow 4 0.5)").unwrap(), Expr::Number(2.0));
+        assert!(run("(pow 2)").is_err());
+        assert_eq!(run("(abs -3)").unwrap(), Expr::Int(3));
+        assert_eq!(run("(abs -2.5)").unwrap(), Expr::Number(2.5));
+        assert_eq!(run("(max 3 7 2)").unwrap(), Expr::Int(7));
+        assert_eq!(run("(min 3 7 2.5)").unwrap(), Expr::Number(2.5));
+        assert!(run("(max)").is_err());
+        assert!(run("(min 1 true)").is_err());
+        // a single operand is its own extremum
+        assert_eq!(run("(max 4)").unwrap(), Expr::Int(4));
+        assert_eq!(run("(min -1.5)").unwrap(), Expr::Number(-1.5));
+        assert!(run("(min)").is_err());
+    }
+
+    #[test]
+    fn check_clamp() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        assert_eq!(run("(clamp 5 0 10)").unwrap(), Expr::Int(5));
+        assert_eq!(run("(clamp -1 0 10)").unwrap(), Expr::Int(0));
+        assert_eq!(run("(clamp 20 0 10)").unwrap(), Expr::Int(10));
+        assert_eq!(
+            run("(clamp 0.5 0 1/4)").unwrap(),
+            Expr::Ratio { num: 1, den: 4 }
+        );
+        assert_eq!(run("(clamp 3 3 3)").unwrap(), Expr::Int(3));
+        assert_eq!(
+            run("(clamp 5 10 0)").unwrap_err().to_string(),
+            "`clamp` bounds are out of order: `10` is above `0`"
+        );
+        assert!(run("(clamp 5 0)").is_err());
+        assert!(run("(clamp 5 0 \"10\")").is_err());
+    }
+
+    #[test]
+    fn check_inc_dec() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        assert_eq!(run("(inc 41)").unwrap(), Expr::Int(42));
+        assert_eq!(run("(dec 0)").unwrap(), Expr::Int(-1));
+        assert_eq!(run("(inc 1.5)").unwrap(), Expr::Number(2.5));
+        assert_eq!(run("(dec 1/2)").unwrap(), Expr::Ratio { num: -1, den: 2 });
+        assert_eq!(
+            run("(inc 9223372036854775807)").unwrap().to_string(),
+            "9223372036854775808"
+        );
+        assert_eq!(run("(map inc (list 1 2))").unwrap().to_string(), "(2 3)");
+
+        let err = run("(inc 1 2)").unwrap_err();
+        assert!(
+            matches!(
+                err.downcast_ref::<Error>(),
+                Some(Error::ArityMismatch { .. })
+            ),
+            "{err}"
+        );
+        assert_eq!(err.to_string(), "`inc` expected 1 argument, got 2");
+        assert!(run("(dec)").is_err());
+        let err = run("(dec \"1\")").unwrap_err();
+        assert!(
+            matches!(err.downcast_ref::<Error>(), Some(Error::TypeError { .. })),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn check_numeric_predicates() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+        let yes = Expr::Bool(true);
+        let no = Expr::Bool(false);
+
+        assert_eq!(run("(zero? 0)").unwrap(), yes);
+        assert_eq!(run("(zero? 0.0)").unwrap(), yes);
+        assert_eq!(run("(zero? 1/2)").unwrap(), no);
+        assert_eq!(run("(positive? 3)").unwrap(), yes);
+        assert_eq!(run("(positive? 0)").unwrap(), no);
+        assert_eq!(run("(negative? -0.5)").unwrap(), yes);
+        assert_eq!(run("(negative? 0)").unwrap(), no);
+        assert_eq!(run("(negative? (- (pow 2 70)))").unwrap(), yes);
+
+        assert_eq!(run("(even? 4)").unwrap(), yes);
+        assert_eq!(run("(even? -3)").unwrap(), no);
+        assert_eq!(run("(even? 4.0)").unwrap(), yes);
+        assert_eq!(run("(odd? 7)").unwrap(), yes);
+        assert_eq!(run("(odd? -2)").unwrap(), no);
+        assert_eq!(run("(even? (pow 2 70))").unwrap(), yes);
+        assert_eq!(run("(odd? (- 1 (pow 2 70)))").unwrap(), yes);
+
+        assert_eq!(
+            run("(even? 2.5)").unwrap_err().to_string(),
+            "`even?` expects a whole number, got `2.5`"
+        );
+        assert!(run("(odd? 1/2)").is_err());
+        assert!(run("(zero? \"0\")").is_err());
+        assert!(run("(positive? 1 2)").is_err());
+    }
+
+    #[test]
+    fn check_rounding() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        assert_eq!(run("(floor 2.7)").unwrap(), Expr::Int(2));
+        assert_eq!(run("(floor -2.1)").unwrap(), Expr::Int(-3));
+        assert_eq!(run("(ceil 2.1)").unwrap(), Expr::Int(3));
+        assert_eq!(run("(ceil -2.7)").unwrap(), Expr::Int(-2));
+        assert_eq!(run("(truncate -2.7)").unwrap(), Expr::Int(-2));
+        assert_eq!(run("(truncate 2.7)").unwrap(), Expr::Int(2));
+        assert_eq!(run("(round 2.4)").unwrap(), Expr::Int(2));
+        assert_eq!(run("(round -2.6)").unwrap(), Expr::Int(-3));
+        // ties go to the even neighbour
+        assert_eq!(run("(round 2.5)").unwrap(), Expr::Int(2));
+        assert_eq!(run("(round 3.5)").unwrap(), Expr::Int(4));
+        assert_eq!(run("(round -2.5)").unwrap(), Expr::Int(-2));
+
+        // ints are already whole, and ratios round exactly
+        assert_eq!(run("(floor 7)").unwrap(), Expr::Int(7));
+        assert_eq!(run("(floor -7/2)").unwrap(), Expr::Int(-4));
+        assert_eq!(run("(ceil -7/2)").unwrap(), Expr::Int(-3));
+        assert_eq!(run("(truncate -7/2)").unwrap(), Expr::Int(-3));
+        assert_eq!(run("(round 5/2)").unwrap(), Expr::Int(2));
+        assert_eq!(run("(round 7/2)").unwrap(), Expr::Int(4));
+        assert_eq!(run("(round -7/2)").unwrap(), Expr::Int(-4));
+        assert_eq!(run("(round 5/3)").unwrap(), Expr::Int(2));
+        // floats beyond `i64` become bigints
+        assert_eq!(
+            run("(floor 1e20)").unwrap().to_string(),
+            "100000000000000000000"
+        );
+
+        assert!(run("(floor (exp 1000))").is_err());
+        assert!(run("(round \"1\")").is_err());
+        assert!(run("(ceil 1 2)").is_err());
+    }
+
+    #[test]
+    fn check_mod() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        assert_eq!(run("(mod 10 3)").unwrap(), Expr::Int(1));
+        assert_eq!(run("(mod -7 3)").unwrap(), Expr::Int(2));
+        assert_eq!(run("(mod 7 -3)").unwrap(), Expr::Int(-2));
+        assert_eq!(run("(mod -6 3)").unwrap(), Expr::Int(0));
+        assert_eq!(run("(mod 5.5 2)").unwrap(), Expr::Number(1.5));
+        assert_eq!(run("(mod 1 0)").unwrap_err().to_string(), "mod by zero");
+        assert!(run("(mod 1)").is_err());
+    }
+
+    #[test]
+    fn check_equal() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        assert_eq!(
+            run("(equal? (list 1 2) (list 1 2))").unwrap(),
+            Expr::Bool(true)
+        );
+        assert_eq!(
+            run("(equal? '(1 (a \"b\")) '(1 (a \"b\")))").unwrap(),
+            Expr::Bool(true)
+        );
+        assert_eq!(
+            run("(equal? '(1 (2)) '(1 (3)))").unwrap(),
+            Expr::Bool(false)
+        );
+        assert_eq!(run("(equal? 1 \"1\")").unwrap(), Expr::Bool(false));
+        assert_eq!(run("(equal? 1 1.0)").unwrap(), Expr::Bool(false));
+        assert_eq!(run("(equal? true true)").unwrap(), Expr::Bool(true));
+        // functions are never equal
+        assert_eq!(run("(equal? + +)").unwrap(), Expr::Bool(false));
+        run("(define f (lambda (x) x))").unwrap();
+        assert_eq!(run("(equal? f f)").unwrap(), Expr::Bool(false));
+        assert!(run("(equal? 1)").is_err());
+    }
+
+    #[test]
+    fn check_cond() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        run("(define x 5)").unwrap();
+        // the third clause would error if its body were evaluated
+        assert_eq!(
+            run("(cond ((< x 0) 'negative) ((< x 10) 'small) (else (undefined)))").unwrap(),
+            Expr::Symbol(Symbol::new("small"))
+        );
+        assert_eq!(
+            run("(cond ((< x 0) 'negative) (else (define y 1) (+ y 1)))").unwrap(),
+            Expr::Int(2)
+        );
+        assert_eq!(run("(cond (false 1))").unwrap(), Expr::nil());
+        assert!(run("(cond (true))").is_err());
+        assert!(run("(cond 1)").is_err());
+    }
+
+    #[test]
+    fn check_while() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        run("(define i 0)").unwrap();
+        run("(define total 0)").unwrap();
+        assert_eq!(
+            run("(while (< i 5) (set! total (+ total i)) (set! i (+ i 1)))").unwrap(),
+            Expr::nil()
+        );
+        assert_eq!(run("i").unwrap(), Expr::Int(5));
+        assert_eq!(run("total").unwrap(), Expr::Int(10));
+        // a false condition never runs the body, and the result is still nil
+        assert_eq!(run("(while false (undefined))").unwrap(), Expr::nil());
+        assert_eq!(
+            run("(while (< i 5) (set! i (+ i 1)) i)").unwrap(),
+            Expr::nil()
+        );
+        assert_eq!(run("i").unwrap(), Expr::Int(5));
+        // looping doesn't nest evaluation, so it isn't bounded by the depth limit
+        run("(define n 0)").unwrap();
+        run("(while (< n 20000) (set! n (+ n 1)))").unwrap();
+        assert_eq!(run("n").unwrap(), Expr::Int(20000));
+        // errors in the condition or the body end the loop
+        assert!(run("(while (undefined))").is_err());
+        assert!(run("(while true (car 1))").is_err());
+        assert!(run("(while)").is_err());
+    }
+
+    #[test]
+    fn check_dotimes() {
+        let mut env = default_env();
+        let out = SharedBuffer::default();
+        env.set_output(Box::new(out.clone()));
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        assert_eq!(
+            run("(dotimes (i 3) (print i) (newline))").unwrap(),
+            Expr::nil()
+        );
+        assert_eq!(out.text(), "0\n1\n2\n");
+        // the counter only exists inside the loop
+        assert!(matches!(
+            run("i").unwrap_err().downcast_ref::<Error>(),
+            Some(Error::UnboundSymbol(_))
+        ));
+        // each pass gets its own scope, so closures keep their own `i`
+        run("(define fs '())").unwrap();
+        run("(dotimes (i (+ 1 2)) (set! fs (cons (lambda () i) fs)))").unwrap();
+        assert_eq!(
+            run("(map (lambda (f) (f)) fs)").unwrap().to_string(),
+            "(2 1 0)"
+        );
+        // a count of zero never runs the body
+        assert_eq!(run("(dotimes (i 0) (undefined))").unwrap(), Expr::nil());
+
+        assert!(run("(dotimes (i -1))").is_err());
+        assert!(run("(dotimes (i 2.5))").is_err());
+        assert!(run("(dotimes (i))").is_err());
+        assert!(run("(dotimes i 3)").is_err());
+    }
+
+    #[test]
+    fn check_let_destructuring() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        assert_eq!(
+            run("(let (((a b c) (list 1 2 3))) (+ a b c))").unwrap(),
+            Expr::Int(6)
+        );
+        // patterns nest, and sit alongside plain names
+        assert_eq!(
+            run("(let (((a (b c)) '(1 (2 3))) (d 4)) (list a b c d))")
+                .unwrap()
+                .to_string(),
+            "(1 2 3 4)"
+        );
+        assert_eq!(run("(let ((() nil)) 1)").unwrap(), Expr::Int(1));
+
+        // a rest pattern takes whatever is left, even nothing
+        assert_eq!(
+            run("(let (((a . rest) (list 1 2 3))) (list a rest))")
+                .unwrap()
+                .to_string(),
+            "(1 (2 3))"
+        );
+        assert_eq!(
+            run("(let (((a b . rest) (list 1 2))) (list a b rest))")
+                .unwrap()
+                .to_string(),
+            "(1 2 ())"
+        );
+        assert_eq!(
+            run("(let (((a . (b c)) (list 1 2 3))) (+ a b c))").unwrap(),
+            Expr::Int(6)
+        );
+
+        assert_eq!(
+            run("(let (((a b) (list 1 2 3))) a)")
+                .unwrap_err()
+                .to_string(),
+            "`(1 2 3)` doesn't match the pattern `(a b)`"
+        );
+        assert!(run("(let (((a b c) (list 1 2))) a)").is_err());
+        assert!(run("(let (((a b . rest) (list 1))) a)").is_err());
+        assert!(run("(let (((a b) 5)) a)").is_err());
+        assert!(run("(let (((a (b c)) '(1 2))) a)").is_err());
+        assert!(run("(let (((a .) (list 1))) a)").is_err());
+        assert!(run("(let (((a . b c) (list 1 2 3))) a)").is_err());
+        assert!(run("(let (((a 1) (list 1 2))) a)").is_err());
+    }
+
+    #[test]
+    fn check_values() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        assert_eq!(
+            run("(divmod 17 5)").unwrap(),
+            Expr::Values(Rc::new(vec![Expr::Int(3), Expr::Int(2)]))
+        );
+        assert_eq!(run("(divmod 17 5)").unwrap().to_string(), "3 2");
+        assert_eq!(
+            run("(let-values (((q r) (divmod 17 5))) (list q r))")
+                .unwrap()
+                .to_string(),
+            "(3 2)"
+        );
+        // the quotient is floored, so the remainder agrees with `mod`
+        assert_eq!(run("(divmod -17 5)").unwrap().to_string(), "-4 3");
+        assert_eq!(run("(divmod 17 -5)").unwrap().to_string(), "-4 -3");
+        assert_eq!(
+            run("(list (mod -17 5) (mod 17 -5))").unwrap().to_string(),
+            "(3 -3)"
+        );
+        assert_eq!(
+            run("(divmod (pow 10 20) 7)").unwrap().to_string(),
+            "14285714285714285714 2"
+        );
+
+        // several bindings, made from `values` or a plain value
+        assert_eq!(
+            run("(let-values (((a b c) (values 1 2 3)) ((d) 4) (() (values))) (+ a b c d))")
+                .unwrap(),
+            Expr::Int(10)
+        );
+        assert_eq!(run("(values 1)").unwrap(), Expr::Int(1));
+        assert_eq!(
+            run("(type-of (values 1 2))").unwrap(),
+            Expr::Symbol(Symbol::new("values"))
+        );
+        // bindings see the outer scope, not each other
+        run("(define q 100)").unwrap();
+        assert_eq!(
+            run("(let-values (((q r) (divmod 7 2)) ((x) q)) (list q r x))")
+                .unwrap()
+                .to_string(),
+            "(3 1 100)"
+        );
+
+        assert_eq!(
+            run("(let-values (((q) (divmod 17 5))) q)")
+                .unwrap_err()
+                .to_string(),
+            "`let-values` expected 1 value for `(q)`, got 2"
+        );
+        assert_eq!(
+            run("(let-values (((a b c) (divmod 17 5))) a)")
+                .unwrap_err()
+                .to_string(),
+            "`let-values` expected 3 values for `(a b c)`, got 2"
+        );
+        assert!(run("(let-values ((a (values 1 2))) a)").is_err());
+        assert!(run("(let-values (((1 b) (values 1 2))) b)").is_err());
+        assert!(run("(divmod 1 0)").is_err());
+        assert!(run("(divmod 1.5 1)").is_err());
+        assert!(run("(divmod 1)").is_err());
+    }
+
+    #[test]
+    fn check_case() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        run("(define grade (lambda (n) (case n ((1 2) \"low\") ((3 4 5) \"mid\") (else \"high\"))))")
+            .unwrap();
+        assert_eq!(run("(grade 2)").unwrap(), Expr::String("low".to_owned()));
+        assert_eq!(run("(grade 4)").unwrap(), Expr::String("mid".to_owned()));
+        assert_eq!(run("(grade 9)").unwrap(), Expr::String("high".to_owned()));
+        // single keys, symbols and strings, with the key expression evaluated once
+        run("(define calls 0)").unwrap();
+        assert_eq!(
+            run("(case (begin (set! calls (+ calls 1)) 'b) (a 1) (b 2) (b 3))").unwrap(),
+            Expr::Int(2)
+        );
+        assert_eq!(run("calls").unwrap(), Expr::Int(1));
+        assert_eq!(
+            run(r#"(case "x" (("y" "x") 'found))"#).unwrap(),
+            run("'found").unwrap()
+        );
+        // keys compare as `equal?` does, so `1.0` isn't `1`
+        assert_eq!(
+            run("(case 1.0 ((1) 'int) ((1.0) 'float))").unwrap(),
+            run("'float").unwrap()
+        );
+        // skipped clauses aren't evaluated, and nothing matching is nil
+        assert_eq!(run("(case 1 ((2) (undefined)))").unwrap(), Expr::nil());
+        assert!(run("(case)").is_err());
+        assert!(run("(case 1 (1))").is_err());
+    }
+
+    #[test]
+    fn check_run_file() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("largo-run-file-{}.largo", std::process::id()));
+
+        fs::write(
+            &path,
+            "; squares\n(define sq (lambda (x) (* x x)))\n(sq 4)\n",
+        )
+        .unwrap();
+        assert!(run_file(&path).is_ok());
+        let mut env = default_env();
+        let source = fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            eval_file_source(&source, &mut env).unwrap(),
+            Some(Expr::Int(16))
+        );
+        assert_eq!(eval_file_source("; nothing", &mut env).unwrap(), None);
+
+        fs::write(&path, "(define x 1)\n\n  (+ x y)\n").unwrap();
+        let err = format!("{:#}", run_file(&path).unwrap_err());
+        assert!(err.contains(&path.display().to_string()));
+        assert!(err.contains("line:3 col:3"));
+        assert!(err.contains("Unbound symbol `y`"));
+
+        fs::remove_file(&path).unwrap();
+        let err = run_file(&path).unwrap_err().to_string();
+        assert!(err.starts_with("Could not read"));
+    }
+
+    #[test]
+    fn check_load() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("largo-load-{}.largo", std::process::id()));
+        fs::write(
+            &path,
+            "(define double (lambda (x) (* 2 x)))\n(define ten 10)\n",
+        )
+        .unwrap();
+
+        let mut env = default_env();
+        let load = format!("(load \"{}\")", path.display());
+        assert_eq!(eval_str(&load, &mut env).unwrap(), Expr::Int(10));
+        assert_eq!(eval_str("(double ten)", &mut env).unwrap(), Expr::Int(20));
+
+        fs::write(&path, "(define broken (+ 1").unwrap();
+        assert!(eval_str(&load, &mut env).is_err());
+        fs::remove_file(&path).unwrap();
+        let err = eval_str(&load, &mut env).unwrap_err().to_string();
+        assert!(err.starts_with("Could not read"));
+        assert!(eval_str("(load 1)", &mut env).is_err());
+        // the env is still usable after a failed load
+        assert_eq!(eval_str("(double 1)", &mut env).unwrap(), Expr::Int(2));
+    }
+
+    #[test]
+    fn check_is_incomplete() {
+        assert!(is_incomplete("(define f (lambda (x)"));
+        assert!(is_incomplete("(define f (lambda (x)\n  (* x x)"));
+        assert!(!is_incomplete("(define f (lambda (x)\n  (* x x)))"));
+        assert!(!is_incomplete("(+ 1 2))"));
+        assert!(!is_incomplete("(+ 1 \"2"));
+        assert!(!is_incomplete("quit"));
+    }
+
+    #[test]
+    fn check_env_command() {
+        let mut env = default_env();
+        eval_line("(define x 2)", &mut env).unwrap();
+        eval_line("(define square (lambda (n) (* n n)))", &mut env).unwrap();
+        let listing = eval_line(":env", &mut env).unwrap().unwrap();
+        assert!(listing.lines().any(|line| line == "x = 2"));
+        assert!(listing.lines().any(|line| line == "square = <function>"));
+        assert!(listing.lines().any(|line| line == "+ = <function>"));
+        assert!(eval_line(":nope", &mut env).is_err());
+
+        let inner = Env::with_parent(HashMap::new(), Rc::new(env.clone()));
+        inner.insert(Symbol::new("y"), Expr::Int(3));
+        let listing = describe_env(&inner);
+        assert!(listing.starts_with("y = 3\n; enclosing scope\n"));
+    }
+
+    #[test]
+    fn check_help_command() {
+        let mut env = default_env();
+        let help = eval_line(":help", &mut env).unwrap().unwrap();
+        let lines: Vec<&str> = help.lines().collect();
+        assert_eq!(lines[0], "Special forms:");
+        assert!(lines[1].split_whitespace().any(|name| name == "lambda"));
+        assert_eq!(lines[2], "Builtins:");
+        assert!(lines[3].split_whitespace().any(|name| name == "car"));
+
+        // every listed form is one `eval_special_form` actually handles
+        for form in SPECIAL_FORMS {
+            let op = Expr::Symbol(Symbol::new(form));
+            assert!(eval_special_form(&op, &[], &mut env).is_some(), "{}", form);
+        }
+    }
+
+    #[test]
+    fn check_negative_literals() {
+        assert_eq!(parse_atom("-5"), Expr::Int(-5));
+        assert_eq!(parse_atom("-2.5"), Expr::Number(-2.5));
+        assert_eq!(parse_atom("-"), Expr::Symbol(Symbol::new("-")));
+        assert_eq!(parse_atom("-abc"), Expr::Symbol(Symbol::new("-abc")));
+
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env).unwrap();
+        assert_eq!(run("(- 3 -5)"), Expr::Int(8));
+        assert_eq!(run("(+ -1.5 1)"), Expr::Number(-0.5));
+    }
+
+    #[test]
+    fn check_radix_literals() {
+        assert_eq!(parse_atom("0xFF"), Expr::Int(255));
+        assert_eq!(parse_atom("0xff"), Expr::Int(255));
+        assert_eq!(parse_atom("0b1010"), Expr::Int(10));
+        assert_eq!(parse_atom("-0x10"), Expr::Int(-16));
+        // digits too many for `i64` make a bigint
+        assert_eq!(
+            parse_atom("0x8000000000000000").to_string(),
+            "9223372036854775808"
+        );
+        assert_eq!(parse_atom("-0x8000000000000000"), Expr::Int(i64::MIN));
+        assert_eq!(
+            parse_atom(&format!("0b1{}", "0".repeat(64))).to_string(),
+            "18446744073709551616"
+        );
+        assert_eq!(
+            eval_str("(- 0x10000000000000000 1)", &mut default_env())
+                .unwrap()
+                .to_string(),
+            "18446744073709551615"
+        );
+        for token in [
+            "0xG1",
+            "0b102",
+            "0x",
+            "0x-1",
+            "0x1.8",
+            "0x1_0",
+            "0x1FFFFFFFFFFFFFFFFG",
+        ] {
+            assert_eq!(parse_atom(token), Expr::Symbol(Symbol::new(token)));
+        }
+        assert_eq!(
+            eval_str("(+ 0xF 0b1)", &mut default_env()).unwrap(),
+            Expr::Int(16)
+        );
+    }
+
+    #[test]
+    fn check_float_literals() {
+        assert_eq!(parse_atom("1e3"), Expr::Number(1000.0));
+        assert_eq!(parse_atom("2.5E-1"), Expr::Number(0.25));
+        assert_eq!(parse_atom("-1e2"), Expr::Number(-100.0));
+        for token in ["inf", "-inf", "infinity", "NaN", "nan", "e3"] {
+            assert_eq!(parse_atom(token), Expr::Symbol(Symbol::new(token)));
+        }
+        assert!(eval_str("(+ inf 1)", &mut default_env()).is_err());
+    }
+
+    #[test]
+    fn check_symbol_interning() {
+        assert_eq!(Symbol::new("interned"), Symbol::new("interned"));
+        assert_ne!(Symbol::new("interned"), Symbol::new("other"));
+        assert_eq!(Symbol::new("interned").as_str(), "interned");
+        assert_eq!(Symbol::new("if").special_form(), Some("if"));
+        assert_eq!(Symbol::new("interned").special_form(), None);
+
+        let tokens = tokenize("(define interned 1)".to_owned()).unwrap();
+        let (expr, _) = parse(&tokens).unwrap();
+        assert_eq!(expr.to_string(), "(define interned 1)");
+    }
+
+    #[test]
+    fn check_nil() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env).unwrap();
+        assert_eq!(run("(null? (list))"), Expr::Bool(true));
+        assert_eq!(run("(null? nil)"), Expr::Bool(true));
+        assert_eq!(run("(null? '())"), Expr::Bool(true));
+        assert_eq!(run("(null? (cdr (list 1)))"), Expr::Bool(true));
+        assert_eq!(run("(null? 1)"), Expr::Bool(false));
+        assert_eq!(run("(null? (list 1))"), Expr::Bool(false));
+        assert_eq!(run("(null? false)"), Expr::Bool(false));
+        assert_eq!(run("nil").to_string(), "()");
+        // nil is not false
+        assert_eq!(run("(if nil 1 2)"), Expr::Int(1));
+        // `()` needn't be quoted
+        assert_eq!(run("()"), Expr::nil());
+        assert_eq!(run("(list () '())").to_string(), "(() ())");
+        assert_eq!(run("(null? ())"), Expr::Bool(true));
+        assert_eq!(run("(eval (list))"), Expr::nil());
+    }
+
+    #[test]
+    fn check_length() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+        assert_eq!(run("(length (list 1 2 3))").unwrap(), Expr::Int(3));
+        assert_eq!(run("(length (list))").unwrap(), Expr::Int(0));
+        assert_eq!(run("(length \"héllo\")").unwrap(), Expr::Int(5));
+        assert_eq!(run("(length \"\")").unwrap(), Expr::Int(0));
+        assert_eq!(
+            run("(length 5)").unwrap_err().to_string(),
+            "`length` expects a list or a string, got `5`"
+        );
+        assert!(run("(length car)").is_err());
+        assert!(run("(length (list) (list))").is_err());
+    }
+
+    #[test]
+    fn check_append() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+        assert_eq!(
+            run("(append (list 1 2) (list 3) (list 4 5))")
+                .unwrap()
+                .to_string(),
+            "(1 2 3 4 5)"
+        );
+        assert_eq!(
+            run("(append (list 1) (list (list 2)))")
+                .unwrap()
+                .to_string(),
+            "(1 (2))"
+        );
+        assert_eq!(run("(append (list 1 2))").unwrap().to_string(), "(1 2)");
+        assert_eq!(run("(append)").unwrap(), Expr::nil());
+        assert_eq!(
+            run("(append (list 1) 2)").unwrap_err().to_string(),
+            "`append` expects lists, got `2`"
+        );
+    }
+
+    #[test]
+    fn check_reverse() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+        run("(define xs (list 1 2 3))").unwrap();
+        assert_eq!(run("(reverse xs)").unwrap().to_string(), "(3 2 1)");
+        assert_eq!(run("xs").unwrap().to_string(), "(1 2 3)");
+        assert_eq!(run("(reverse (list))").unwrap(), Expr::nil());
+        assert!(run("(reverse 1)").is_err());
+        assert!(run("(reverse xs xs)").is_err());
+    }
+
+    #[test]
+    fn check_string_builtins() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+        assert_eq!(
+            run(r#"(string-concat "foo" "" "bar")"#).unwrap(),
+            Expr::String("foobar".to_owned())
+        );
+        assert_eq!(run("(string-concat)").unwrap(), Expr::String("".to_owned()));
+        assert!(run(r#"(string-concat "a" 1)"#).is_err());
+
+        assert_eq!(
+            run(r#"(substring "hello" 1 3)"#).unwrap(),
+            Expr::String("el".to_owned())
+        );
+        assert_eq!(
+            run(r#"(substring "héllo" 1 4)"#).unwrap(),
+            Expr::String("éll".to_owned())
+        );
+        assert_eq!(
+            run(r#"(substring "héllo" 5 5)"#).unwrap(),
+            Expr::String("".to_owned())
+        );
+        assert_eq!(
+            run(r#"(substring "héllo" 2 6)"#).unwrap_err().to_string(),
+            "`substring` range 2..6 is out of bounds for length 5"
+        );
+        assert!(run(r#"(substring "hello" 3 1)"#).is_err());
+        assert!(run(r#"(substring "hello" -1 2)"#).is_err());
+        assert!(run(r#"(substring "hello" 1)"#).is_err());
+
+        assert_eq!(run(r#"(string-length "héllo")"#).unwrap(), Expr::Int(5));
+        assert!(run("(string-length (list))").is_err());
+    }
+
+    #[test]
+    fn check_number_string_conversions() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+        assert_eq!(
+            run("(number->string 42)").unwrap(),
+            Expr::String("42".to_owned())
+        );
+        assert_eq!(
+            run("(number->string -2.5)").unwrap(),
+            Expr::String("-2.5".to_owned())
+        );
+        assert!(run(r#"(number->string "42")"#).is_err());
+
+        assert_eq!(
+            run("(string->number (number->string 42))").unwrap(),
+            Expr::Int(42)
+        );
+        assert_eq!(
+            run("(string->number (number->string 0.5))").unwrap(),
+            Expr::Number(0.5)
+        );
+        assert_eq!(run(r#"(string->number "0xFF")"#).unwrap(), Expr::Int(255));
+        assert_eq!(run(r#"(string->number "abc")"#).unwrap(), Expr::Bool(false));
+        assert_eq!(
+            run(r#"(string->number "1_000")"#).unwrap(),
+            Expr::Bool(false)
+        );
+        assert_eq!(
+            run(r#"(string->number "true")"#).unwrap(),
+            Expr::Bool(false)
+        );
+        assert!(run("(string->number 42)").is_err());
+    }
+
+    #[test]
+    fn check_set() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        run("(define x 1)").unwrap();
+        assert_eq!(run("(let ((y 2)) (set! x (+ x y)))").unwrap(), Expr::Int(3));
+        assert_eq!(run("x").unwrap(), Expr::Int(3));
+        // a shadowing binding is the one that changes
+        assert_eq!(
+            run("(let ((x 10)) (begin (set! x 20) x))").unwrap(),
+            Expr::Int(20)
+        );
+        assert_eq!(run("x").unwrap(), Expr::Int(3));
+        // closures see the change
+        run("(define counter 0)").unwrap();
+        run("(define bump (lambda () (set! counter (+ counter 1))))").unwrap();
+        run("(bump)").unwrap();
+        assert_eq!(run("(bump)").unwrap(), Expr::Int(2));
+        assert_eq!(run("counter").unwrap(), Expr::Int(2));
+
+        assert_eq!(
+            run("(set! undefined 1)").unwrap_err().to_string(),
+            "Unbound symbol `undefined`"
+        );
+        assert!(run("undefined").is_err());
+        assert!(run("(set! x)").is_err());
+        assert!(run("(set! 1 2)").is_err());
+    }
+
+    #[test]
+    fn check_let_star() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        // the same bindings only work when each can see the one before
+        assert!(run("(let ((x 1) (y (+ x 1))) y)").is_err());
+        assert_eq!(run("(let* ((x 1) (y (+ x 1))) y)").unwrap(), Expr::Int(2));
+        // later bindings shadow earlier ones
+        assert_eq!(run("(let* ((x 1) (x (* x 10))) x)").unwrap(), Expr::Int(10));
+        assert_eq!(run("(let* () 5)").unwrap(), Expr::Int(5));
+        // bindings are only visible inside the body
+        assert!(run("x").is_err());
+        assert!(run("(let* ((x)) x)").is_err());
+        assert!(run("(let* ((x 1)))").is_err());
+    }
+
+    #[test]
+    fn check_when_unless() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        assert_eq!(run("(when (< 1 2) 1 2 3)").unwrap(), Expr::Int(3));
+        assert_eq!(run("(when false (undefined-fn))").unwrap(), Expr::nil());
+        assert_eq!(run("(unless false 1 2)").unwrap(), Expr::Int(2));
+        assert_eq!(run("(unless true (undefined-fn))").unwrap(), Expr::nil());
+        // an empty body is nil too
+        assert_eq!(run("(when true)").unwrap(), Expr::nil());
+        // nil is truthy
+        assert_eq!(run("(when nil 1)").unwrap(), Expr::Int(1));
+        // the body may have side effects
+        run("(define x 0)").unwrap();
+        run("(when true (define x 1) (define x (+ x 1)))").unwrap();
+        assert_eq!(run("x").unwrap(), Expr::Int(2));
+
+        assert!(run("(when)").is_err());
+        assert!(run("(unless)").is_err());
+        assert!(run("(when (undefined-fn) 1)").is_err());
+    }
+
+    #[test]
+    fn check_vector() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        run("(define v (vector 1 (+ 1 1) \"three\"))").unwrap();
+        assert_eq!(run("v").unwrap().to_string(), r#"#(1 2 "three")"#);
+        assert_eq!(run("(vector)").unwrap().to_string(), "#()");
+        // a literal evaluates to itself, leaving its items unevaluated
+        assert_eq!(
+            run("(equal? #(1 2 \"three\") (vector 1 2 \"three\"))").unwrap(),
+            Expr::Bool(true)
+        );
+        assert_eq!(
+            run("(vector-ref #((+ 1 2)) 0)").unwrap().to_string(),
+            "(+ 1 2)"
+        );
+        assert_eq!(run("(vector-length #())").unwrap(), Expr::Int(0));
+        assert_eq!(run("(vector-ref v 0)").unwrap(), Expr::Int(1));
+        assert_eq!(
+            run("(vector-ref v 2)").unwrap(),
+            Expr::String("three".to_owned())
+        );
+        assert_eq!(run("(vector-length v)").unwrap(), Expr::Int(3));
+        assert_eq!(run("(vector-length (vector))").unwrap(), Expr::Int(0));
+
+        assert_eq!(
+            run("(vector-ref v 3)").unwrap_err().to_string(),
+            "`vector-ref` index 3 is out of bounds for length 3"
+        );
+        assert!(run("(vector-ref v -1)").is_err());
+        assert!(run("(vector-ref (list 1 2) 0)").is_err());
+        assert!(run("(vector-length (list 1 2))").is_err());
+
+        // vectors and lists with the same items are still different values
+        assert_eq!(
+            run("(equal? v (vector 1 2 \"three\"))").unwrap(),
+            Expr::Bool(true)
+        );
+        assert_eq!(
+            run("(equal? (vector 1) (list 1))").unwrap(),
+            Expr::Bool(false)
+        );
+    }
+
+    #[test]
+    fn check_dict() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        run(r#"(define d (make-dict "a" 1 'b (list 2)))"#).unwrap();
+        assert_eq!(run(r#"(dict-get d "a")"#).unwrap(), Expr::Int(1));
+        assert_eq!(run("(dict-get d 'b)").unwrap().to_string(), "(2)");
+        // missing keys are nil; a symbol and a string with the same text differ
+        assert_eq!(run(r#"(dict-get d "b")"#).unwrap(), Expr::nil());
+        assert_eq!(run("(dict-get (make-dict) 1)").unwrap(), Expr::nil());
+
+        run("(define e (dict-set d 1 true))").unwrap();
+        assert_eq!(run("(dict-get e 1)").unwrap(), Expr::Bool(true));
+        assert_eq!(run(r#"(dict-get e "a")"#).unwrap(), Expr::Int(1));
+        run(r#"(define e (dict-set e "a" 5))"#).unwrap();
+        assert_eq!(run(r#"(dict-get e "a")"#).unwrap(), Expr::Int(5));
+        // the original is left alone
+        assert_eq!(run(r#"(dict-get d "a")"#).unwrap(), Expr::Int(1));
+        assert_eq!(run("(dict-get d 1)").unwrap(), Expr::nil());
+
+        assert_eq!(run("d").unwrap().to_string(), r#"{"a" 1 b (2)}"#);
+        assert_eq!(run("(make-dict)").unwrap().to_string(), "{}");
+        assert_eq!(
+            run(r#"(equal? d (make-dict 'b (list 2) "a" 1))"#).unwrap(),
+            Expr::Bool(true)
+        );
+
+        assert!(run("(make-dict 1)").is_err());
+        assert_eq!(
+            run("(make-dict 1.5 1)").unwrap_err().to_string(),
+            "`1.5` cannot be a dict key"
+        );
+        assert!(run("(dict-get d (list))").is_err());
+        assert!(run("(dict-set d 1)").is_err());
+        assert!(run("(dict-get (list) 1)").is_err());
+    }
+
+    #[test]
+    fn check_print() {
+        let text = |s: &str| {
+            let tokens = tokenize(s.to_owned()).unwrap();
+            let args = parse_all(&tokens).unwrap();
+            display_text(&args)
+        };
+        assert_eq!(text(r#""hello""#), "hello");
+        assert_eq!(text(r#""a \"quoted\" word""#), r#"a "quoted" word"#);
+        assert_eq!(text(r#""x =" 1.5 (1 "two")"#), r#"x = 1.5 (1 "two")"#);
+        assert_eq!(text(""), "");
+
+        let mut env = default_env();
+        let out = SharedBuffer::default();
+        env.set_output(Box::new(out.clone()));
+        let mut run = |s: &str| eval_str(s, &mut env);
+        assert_eq!(run(r#"(print "hello")"#).unwrap(), Expr::nil());
+        assert_eq!(run("(display 1 2)").unwrap(), Expr::nil());
+        assert_eq!(run("(newline)").unwrap(), Expr::nil());
+        assert!(run("(newline 1)").is_err());
+        // scopes created by calls share the root's output
+        run(r#"((lambda (x) (print x)) "inner")"#).unwrap();
+        assert_eq!(out.text(), "hello1 2\ninner");
+    }
+
+    #[test]
+    fn check_write() {
+        let mut env = default_env();
+        let out = SharedBuffer::default();
+        env.set_output(Box::new(out.clone()));
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        assert_eq!(run(r#"(write "a\"b")"#).unwrap(), Expr::nil());
+        assert_eq!(out.text(), r#""a\"b""#);
+        out.0.borrow_mut().clear();
+        run(r#"(display "a\"b")"#).unwrap();
+        assert_eq!(out.text(), r#"a"b"#);
+        out.0.borrow_mut().clear();
+        run(r#"(write #\a "x\ny" '(1 "two" #\space))"#).unwrap();
+        assert_eq!(out.text(), r#"#\a "x\ny" (1 "two" #\space)"#);
+
+        // what `repr` gives back reads as the same value
+        assert_eq!(
+            run(r#"(repr "tab\there")"#).unwrap(),
+            Expr::String(r#""tab\there""#.to_owned())
+        );
+        run(r#"(define data (list 'a "b\\c" #\( 1.0 3/4 (vector 2 "v") (make-dict 'k (vector 1) "s" '(x))))"#)
+            .unwrap();
+        assert_eq!(
+            run("(repr data)").unwrap(),
+            Expr::String(r#"(a "b\\c" #\( 1.0 3/4 #(2 "v") {"s" (x) k #(1)})"#.to_owned())
+        );
+        assert_eq!(
+            run("(equal? (read (repr data)) data)").unwrap(),
+            Expr::Bool(true)
+        );
+        assert_eq!(
+            run("(equal? (read (repr (vector 1 2))) (vector 1 2))").unwrap(),
+            Expr::Bool(true)
+        );
+        assert_eq!(
+            run("(equal? (read (repr (make-dict 1 2))) (make-dict 1 2))").unwrap(),
+            Expr::Bool(true)
+        );
+        assert!(run("(repr)").is_err());
+    }
+
+    #[test]
+    fn check_env_func() {
+        let mut env = default_env();
+        // binds each symbol it is given to itself, in the caller's scope
+        env.insert(
+            Symbol::new("self-define"),
+            Expr::EnvFunc(|args: &[Expr], env: &mut Env| -> Result<Expr> {
+                for arg in args {
+                    let Expr::Symbol(name) = arg else {
+                        return Err(Error::Reason("expected symbols".to_owned()).into());
+                    };
+                    env.insert(*name, arg.clone());
+                }
+                Ok(Expr::nil())
+            }),
+        );
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        run("(self-define 'a 'b)").unwrap();
+        assert_eq!(run("a").unwrap(), Expr::Symbol(Symbol::new("a")));
+        assert_eq!(run("b").unwrap(), Expr::Symbol(Symbol::new("b")));
+        // called from inside a `let`, the binding lands in the `let` scope
+        assert_eq!(
+            run("(let ((x 1)) (begin (self-define 'c) c))").unwrap(),
+            Expr::Symbol(Symbol::new("c"))
+        );
+        assert!(run("c").is_err());
+        // env-aware builtins can be passed around like any other function
+        run("(apply self-define (list 'd))").unwrap();
+        assert_eq!(run("d").unwrap(), Expr::Symbol(Symbol::new("d")));
+        run("(map self-define (list 'e))").unwrap();
+        assert_eq!(run("e").unwrap(), Expr::Symbol(Symbol::new("e")));
+        assert_eq!(run("self-define").unwrap().to_string(), "Function");
+        assert!(run("(self-define 1)").is_err());
+    }
+
+    #[test]
+    fn check_eval_builtin() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        assert_eq!(run("(eval (list '+ 1 2))").unwrap(), Expr::Int(3));
+        assert_eq!(run("(eval '(* 2 (+ 1 2)))").unwrap(), Expr::Int(6));
+        assert_eq!(run("(eval 5)").unwrap(), Expr::Int(5));
+        // a quoted symbol is looked up in the calling scope
+        run("(define x 10)").unwrap();
+        assert_eq!(run("(eval 'x)").unwrap(), Expr::Int(10));
+        assert_eq!(run("(let ((x 1)) (eval 'x))").unwrap(), Expr::Int(1));
+        // definitions made by the evaluated form stick
+        run("(eval '(define y 2))").unwrap();
+        assert_eq!(run("y").unwrap(), Expr::Int(2));
+
+        assert!(run("(eval 'undefined)").is_err());
+        assert!(run("(eval)").is_err());
+        assert!(run("(eval 1 2)").is_err());
+    }
+
+    #[test]
+    fn check_read() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        assert_eq!(
+            run(r#"(read "(+ 1 2)")"#).unwrap(),
+            run("'(+ 1 2)").unwrap()
+        );
+        assert_eq!(run(r#"(eval (read "(+ 1 2)"))"#).unwrap(), Expr::Int(3));
+        assert_eq!(
+            run(r#"(read "(define (f x) (list x \"s\" 'q #(1 2.5)))")"#).unwrap(),
+            run(r#"'(define (f x) (list x "s" (quote q) #(1 2.5)))"#).unwrap()
+        );
+        assert_eq!(
+            run(r##"(read "#(1 2)")"##).unwrap(),
+            Expr::Vector(Rc::new(vec![Expr::Int(1), Expr::Int(2)]))
+        );
+        assert_eq!(
+            run(r##"(equal? (read "#(1 (2) #(3))") (vector 1 '(2) (vector 3)))"##).unwrap(),
+            Expr::Bool(true)
+        );
+        assert_eq!(
+            run(r#"(equal? (read "{a 1 \"b\" #(2)}") (make-dict 'a 1 "b" (vector 2)))"#).unwrap(),
+            Expr::Bool(true)
+        );
+        assert_eq!(
+            run("(dict-get {x (+ 1 2)} 'x)").unwrap().to_string(),
+            "(+ 1 2)"
+        );
+        assert_eq!(
+            run("(read \"{}\")").unwrap(),
+            Expr::Dict(Rc::new(HashMap::new()))
+        );
+        assert_eq!(
+            run(r#"(read "  sym ")"#).unwrap(),
+            Expr::Symbol(Symbol::new("sym"))
+        );
+        assert_eq!(
+            run(r#"(car (cdr (read "(1 (2 3))")))"#).unwrap(),
+            run("'(2 3)").unwrap()
+        );
+
+        for bad in [
+            r#"(read "(+ 1")"#,
+            r##"(read "#(1 2")"##,
+            r#"(read "")"#,
+            r#"(read "1 2")"#,
+            "(read 1)",
+            r#"(read "{a}")"#,
+            r#"(read "{1.5 a}")"#,
+            r#"(read "{a 1")"#,
+            r#"(read "(a 1}")"#,
+            "(read)",
+        ] {
+            assert!(run(bad).is_err(), "{bad}");
+        }
+        let err = run(r#"(read "(+ 1 2))")"#).unwrap_err();
+        assert!(
+            matches!(err.downcast_ref::<Error>(), Some(Error::ParseError { .. })),
+            "{err}"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn check_serde() {
+        let round_trip = |expr: &Expr| -> Expr {
+            let json = serde_json::to_string(expr).unwrap();
+            serde_json::from_str(&json).unwrap()
+        };
+        let tokens = tokenize("(+ 1 (list 2 3))".to_owned()).unwrap();
+        let (program, _) = parse(&tokens).unwrap();
+        assert_eq!(round_trip(&program), program);
+        assert_eq!(
+            serde_json::to_string(&program).unwrap(),
+            r#"{"List":[{"Symbol":"+"},{"Int":1},{"List":[{"Symbol":"list"},{"Int":2},{"Int":3}]}]}"#
+        );
+
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env).unwrap();
+        for value in [
+            run("(list 1.5 \"two\" true 'three)"),
+            run("(vector 1 (list))"),
+            run("(make-dict 1 'one \"two\" 2.0 'three (vector))"),
+        ] {
+            assert_eq!(round_trip(&value), value);
+        }
+
+        // functions have nothing meaningful to write out
+        assert!(serde_json::to_string(&run("+")).is_err());
+        assert!(serde_json::to_string(&run("(lambda (x) x)")).is_err());
+        assert!(serde_json::to_string(&run("(list map)")).is_err());
+    }
+
+    #[test]
+    fn check_quasiquote() {
+        assert_eq!(
+            token_texts("`(a ,b ,@c)"),
+            ["`", "(", "a", ",", "b", ",@", "c", ")"]
+        );
+        assert_eq!(token_texts("x,y"), ["x", ",", "y"]);
+
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env).map(|expr| expr.to_string());
+
+        assert_eq!(run("`(1 ,(+ 1 1) 3)").unwrap(), "(1 2 3)");
+        assert_eq!(run("(quasiquote (1 (unquote (+ 1 1))))").unwrap(), "(1 2)");
+        assert_eq!(run("`x").unwrap(), "x");
+        assert_eq!(run("`,(* 2 3)").unwrap(), "6");
+        run("(define xs (list 2 3))").unwrap();
+        assert_eq!(run("`(1 ,xs 4)").unwrap(), "(1 (2 3) 4)");
+        assert_eq!(run("`(1 ,@xs 4)").unwrap(), "(1 2 3 4)");
+        assert_eq!(run("`(0 (1 ,@xs) ,@nil)").unwrap(), "(0 (1 2 3))");
+        // an inner template keeps its unquotes, except those that escape both levels
+        assert_eq!(
+            run("`(a `(b ,(c ,(+ 1 2))))").unwrap(),
+            "(a (quasiquote (b (unquote (c 3)))))"
+        );
+        assert_eq!(
+            run("`(a `(b ,@xs))").unwrap(),
+            "(a (quasiquote (b (unquote-splicing xs))))"
+        );
+
+        assert_eq!(
+            run("`(1 ,@2)").unwrap_err().to_string(),
+            "Cannot splice `2`, not a list"
+        );
+        assert!(run("`(1 ,undefined)").is_err());
+        assert!(run("(quasiquote)").is_err());
+        assert!(run("`").is_err());
+    }
+
+    #[test]
+    fn check_defmacro() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        // arguments arrive unevaluated, so they can be rearranged
+        run("(defmacro infix (a op b) `(,op ,a ,b))").unwrap();
+        assert_eq!(run("(infix 1 + 2)").unwrap(), Expr::Int(3));
+        assert_eq!(run("(infix (* 2 3) - 1)").unwrap(), Expr::Int(5));
+        // and the branch not taken is never evaluated
+        run("(defmacro my-unless (cond then else) `(if ,cond ,else ,then))").unwrap();
+        assert_eq!(
+            run("(my-unless false 1 (undefined-fn))").unwrap(),
+            Expr::Int(1)
+        );
+        // the expansion runs in the caller's scope
+        run("(defmacro swap! (a b) `(let ((tmp ,a)) (begin (set! ,a ,b) (set! ,b tmp))))").unwrap();
+        run("(define x 1)").unwrap();
+        run("(define y 2)").unwrap();
+        run("(swap! x y)").unwrap();
+        assert_eq!(run("(list x y)").unwrap().to_string(), "(2 1)");
+        assert_eq!(
+            run("(let ((x 5) (z 6)) (begin (swap! x z) x))").unwrap(),
+            Expr::Int(6)
+        );
+        assert_eq!(run("infix").unwrap().to_string(), "Macro");
+
+        assert_eq!(
+            run("(infix 1 +)").unwrap_err().to_string(),
+            "Macro expected 3 arguments, got 2"
+        );
+        assert!(run("(defmacro bad (1) 1)").is_err());
+        assert!(run("(defmacro bad)").is_err());
+        assert!(run("(apply infix (list 1 + 2))").is_err());
+    }
+
+    #[test]
+    fn check_char() {
+        assert_eq!(
+            token_texts(r"(f #\( #\  #\space)"),
+            ["(", "f", r"#\(", r"#\ ", r"#\space", ")"]
+        );
+        assert_eq!(parse_atom(r"#\A"), Expr::Char('A'));
+        assert_eq!(parse_atom(r"#\é"), Expr::Char('é'));
+        assert_eq!(parse_atom(r"#\space"), Expr::Char(' '));
+        assert_eq!(parse_atom(r"#\newline"), Expr::Char('\n'));
+
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+        for literal in [r"#\A", r"#\space", r"#\newline", r"#\tab", r"#\(", r"#\;"] {
+            assert_eq!(run(literal).unwrap().to_string(), literal);
+        }
+        assert_eq!(run(r"#\ ").unwrap(), Expr::Char(' '));
+        assert_eq!(run(r"(list #\a #\))").unwrap().to_string(), r"(#\a #\))");
+        assert!(run(r"#\bogus").is_err());
+        assert!(run(r"#\").is_err());
+
+        assert_eq!(run(r"(char->number #\A)").unwrap(), Expr::Int(65));
+        assert_eq!(run("(number->char 955)").unwrap(), Expr::Char('λ'));
+        assert_eq!(
+            run(r"(number->char (char->number #\space))").unwrap(),
+            Expr::Char(' ')
+        );
+        assert_eq!(
+            run("(number->char 55296)").unwrap_err().to_string(),
+            "`55296` is not a valid char"
+        );
+        assert!(run("(number->char -1)").is_err());
+        assert!(run(r#"(char->number "A")"#).is_err());
+        assert_eq!(run(r"(equal? #\a #\a)").unwrap(), Expr::Bool(true));
+    }
+
+    #[test]
+    fn check_type_of() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(&format!("(type-of {s})"), &mut env).unwrap();
+        let cases = [
+            ("true", "bool"),
+            ("'x", "symbol"),
+            ("1", "number"),
+            ("1.5", "number"),
+            (r#""x""#, "string"),
+            (r"#\x", "char"),
+            ("(list)", "list"),
+            ("'(1 2)", "list"),
+            ("(vector 1)", "vector"),
+            ("(make-dict)", "dict"),
+            ("+", "function"),
+            ("map", "function"),
+            ("(lambda (x) x)", "function"),
+            ("(defmacro m () 1)", "macro"),
+        ];
+        for (value, name) in cases {
+            assert_eq!(run(value), Expr::Symbol(Symbol::new(name)), "{value}");
+        }
+        assert!(eval_str("(type-of)", &mut env).is_err());
+    }
+
+    #[test]
+    fn check_type_predicates() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env).unwrap();
+        let cases = [
+            ("number?", ["1", "2.5"], ["\"1\"", "'one"]),
+            ("symbol?", ["'x", "'+"], ["\"x\"", "+"]),
+            ("list?", ["(list)", "'(1 2)"], ["(vector 1)", "\"(1)\""]),
+            ("string?", ["\"\"", "\"x\""], ["'x", r"#\x"]),
+            ("bool?", ["true", "false"], ["nil", "0"]),
+            (
+                "function?",
+                ["+", "(lambda () 1)"],
+                ["'+", "(defmacro m () 1)"],
+            ),
+        ];
+        for (predicate, yes, no) in cases {
+            for value in yes {
+                let call = format!("({predicate} {value})");
+                assert_eq!(run(&call), Expr::Bool(true), "{call}");
+            }
+            for value in no {
+                let call = format!("({predicate} {value})");
+                assert_eq!(run(&call), Expr::Bool(false), "{call}");
+            }
+        }
+        assert_eq!(
+            run("(cond ((number? 'x) 1) ((symbol? 'x) 2))"),
+            Expr::Int(2)
+        );
+        assert!(eval_str("(number? 1 2)", &mut env).is_err());
+    }
+
+    #[test]
+    fn check_bitwise() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        assert_eq!(run("(bit-and 12 10)").unwrap(), Expr::Int(8));
+        assert_eq!(run("(bit-and 15 6 3)").unwrap(), Expr::Int(2));
+        assert_eq!(run("(bit-and)").unwrap(), Expr::Int(-1));
+        assert_eq!(run("(bit-or 12 10)").unwrap(), Expr::Int(14));
+        assert_eq!(run("(bit-or)").unwrap(), Expr::Int(0));
+        assert_eq!(run("(bit-xor 12 10)").unwrap(), Expr::Int(6));
+        assert_eq!(run("(bit-xor 1 1 1)").unwrap(), Expr::Int(1));
+        assert_eq!(run("(bit-not 0)").unwrap(), Expr::Int(-1));
+        assert_eq!(run("(bit-not 0b1010)").unwrap(), Expr::Int(-11));
+        assert_eq!(run("(shift-left 1 4)").unwrap(), Expr::Int(16));
+        assert_eq!(run("(shift-right 0xFF 4)").unwrap(), Expr::Int(15));
+        assert_eq!(run("(shift-right -16 2)").unwrap(), Expr::Int(-4));
+
+        assert_eq!(
+            run("(bit-and 1.5 2)").unwrap_err().to_string(),
+            "`bit-and` expects integers, got `1.5`"
+        );
+        assert!(run(r#"(bit-or 1 "2")"#).is_err());
+        assert!(run("(bit-not 1 2)").is_err());
+        assert!(run("(shift-left 1)").is_err());
+        assert_eq!(
+            run("(shift-left 1 64)").unwrap_err().to_string(),
+            "`shift-left` cannot shift by 64 bits"
+        );
+        assert!(run("(shift-right 1 -1)").is_err());
+    }
+
+    #[test]
+    fn check_trig_exp() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env).unwrap();
+
+        assert!(approx_eq(&run("(sin 0)"), 0.0));
+        assert!(approx_eq(&run("(cos 0)"), 1.0));
+        assert!(approx_eq(&run("(exp 0)"), 1.0));
+        assert!(approx_eq(&run("pi"), std::f64::consts::PI));
+        assert!(approx_eq(&run("(sin (/ pi 2))"), 1.0));
+        assert!(approx_eq(&run("(cos pi)"), -1.0));
+        assert!(approx_eq(&run("(tan (/ pi 4))"), 1.0));
+        assert!(approx_eq(&run("(log (exp 2))"), 2.0));
+        assert!(approx_eq(&run("(log10 1000)"), 3.0));
+        assert!(approx_eq(&run("(exp 1.0)"), std::f64::consts::E));
+
+        assert!(eval_str("(log 0)", &mut env).is_err());
+        assert!(eval_str("(log10 -1)", &mut env).is_err());
+        assert!(eval_str("(sin)", &mut env).is_err());
+        assert!(eval_str("(cos 1 2)", &mut env).is_err());
+        assert!(eval_str(r#"(exp "1")"#, &mut env).is_err());
+        // `pi` is a value, not a function
+        assert!(eval_str("(pi)", &mut env).is_err());
+    }
+
+    #[test]
+    fn check_prelude() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env).unwrap();
+
+        assert_eq!(run("(square 4)"), Expr::Int(16));
+        assert_eq!(run("(cube -2)"), Expr::Int(-8));
+        assert_eq!(run("(list (inc 1) (dec 1))").to_string(), "(2 0)");
+        assert_eq!(run("(map zero? (list 0 1))").to_string(), "(true false)");
+        assert_eq!(run("(filter even? (list 1 2 3 4))").to_string(), "(2 4)");
+        assert_eq!(run("(remove odd? (list 1 2 3 4))").to_string(), "(2 4)");
+        assert_eq!(run("((compose inc square) 3)"), Expr::Int(10));
+        assert_eq!(run("(identity 'x)"), Expr::Symbol(Symbol::new("x")));
+        assert_eq!(run("(sum (list 1 2 3))"), Expr::Int(6));
+        assert_eq!(run("(product (list))"), Expr::Int(1));
+        assert_eq!(run("(last (list 1 2 3))"), Expr::Int(3));
+        assert_eq!(run("(nth (list 1 2 3) 1)"), Expr::Int(2));
+        // prelude functions can be shadowed like any other binding
+        run("(define square (lambda (x) x))");
+        assert_eq!(run("(square 4)"), Expr::Int(4));
+    }
+
+    #[test]
+    fn check_gensym() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env).unwrap();
+
+        assert_eq!(run("(equal? (gensym) (gensym))"), Expr::Bool(false));
+        assert_eq!(run("(symbol? (gensym))"), Expr::Bool(true));
+        let Expr::Symbol(symbol) = run("(gensym)") else {
+            panic!("`gensym` should return a symbol");
+        };
+        assert!(symbol.as_str().starts_with("g__"));
+        // symbols from another environment don't collide either
+        assert_ne!(
+            eval_str("(gensym)", &mut default_env()).unwrap(),
+            Expr::Symbol(symbol)
+        );
+        // a generated name can be bound like any other
+        run("(define g (gensym))");
+        assert_eq!(run("(eval `(let ((,g 1)) ,g))"), Expr::Int(1));
+        assert!(eval_str("(gensym 'x)", &mut env).is_err());
+    }
+
+    #[test]
+    fn check_rest_params() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        run("(define add-all (lambda (first . rest) (fold + first rest)))").unwrap();
+        assert_eq!(run("(add-all 1 2 3 4)").unwrap(), Expr::Int(10));
+        assert_eq!(run("(add-all 1)").unwrap(), Expr::Int(1));
+        assert_eq!(
+            run("(add-all)").unwrap_err().to_string(),
+            "Lambda expected at least 1 argument, got 0"
+        );
+        // with no fixed params every argument lands in the list
+        run("(define args (lambda (. all) all))").unwrap();
+        assert_eq!(run("(args 1 2)").unwrap().to_string(), "(1 2)");
+        assert_eq!(run("(args)").unwrap(), Expr::nil());
+        // macros take rest params too
+        run("(defmacro my-when (cond . body) `(if ,cond (begin ,@body) nil))").unwrap();
+        assert_eq!(run("(my-when true 1 2)").unwrap(), Expr::Int(2));
+
+        assert!(run("(lambda (a .) a)").is_err());
+        assert!(run("(lambda (a . b c) a)").is_err());
+        assert!(run("(lambda (a . 1) a)").is_err());
+    }
+
+    #[test]
+    fn check_optional_params() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        run("(define f (lambda (x (y 10)) (+ x y)))").unwrap();
+        assert_eq!(run("(f 5)").unwrap(), Expr::Int(15));
+        assert_eq!(run("(f 5 1)").unwrap(), Expr::Int(6));
+        assert_eq!(
+            run("(f)").unwrap_err().to_string(),
+            "Lambda expected 1 to 2 arguments, got 0"
+        );
+        assert!(run("(f 1 2 3)").is_err());
+        // defaults are evaluated on each call, in the lambda's own scope
+        run("(define base 1)").unwrap();
+        run("(define g (lambda ((a base) (b (* base 2))) (list a b)))").unwrap();
+        assert_eq!(run("(g)").unwrap().to_string(), "(1 2)");
+        run("(define base 5)").unwrap();
+        assert_eq!(run("(let ((base 100)) (g))").unwrap().to_string(), "(5 10)");
+        assert_eq!(run("(g 0)").unwrap().to_string(), "(0 10)");
+        // a default that is never needed is never evaluated
+        run("(define h (lambda (x (y (undefined-fn))) x))").unwrap();
+        assert_eq!(run("(h 1 2)").unwrap(), Expr::Int(1));
+        assert!(run("(h 1)").is_err());
+        // optional and rest params mix
+        run("(define k (lambda (x (y 2) . more) (list x y more)))").unwrap();
+        assert_eq!(run("(k 1)").unwrap().to_string(), "(1 2 ())");
+        assert_eq!(run("(k 1 3 4 5)").unwrap().to_string(), "(1 3 (4 5))");
+
+        assert!(run("(lambda ((x 1) y) y)").is_err());
+        assert!(run("(lambda ((x)) x)").is_err());
+    }
+
+    #[test]
+    fn check_assert() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        assert_eq!(run("(assert (< 1 2))").unwrap(), Expr::nil());
+        assert_eq!(run("(assert '())").unwrap(), Expr::nil());
+        assert_eq!(
+            run("(assert-eq (list 1 (+ 1 1)) '(1 2))").unwrap(),
+            Expr::nil()
+        );
+        assert_eq!(run(r#"(assert-eq "a" "a")"#).unwrap(), Expr::nil());
+
+        assert_eq!(
+            run("(assert (> 1 2))").unwrap_err().to_string(),
+            "assertion failed"
+        );
+        assert_eq!(
+            run(r#"(assert-eq (+ 1 1) "2")"#).unwrap_err().to_string(),
+            r#"assertion failed: `2` is not equal to `"2"`"#
+        );
+        // ints and floats are different values, however `=` compares them
+        assert!(run("(assert-eq 1 1.0)").is_err());
+        assert!(run("(assert)").is_err());
+        assert!(run("(assert-eq 1)").is_err());
+    }
+
+    #[test]
+    fn check_time() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+        run("(define fact (lambda (n) (if (< n 2) 1 (* n (fact (- n 1))))))").unwrap();
+        assert_eq!(run("(time (fact 10))").unwrap(), run("(fact 10)").unwrap());
+        assert_eq!(
+            run("(time (list 1 \"two\"))").unwrap(),
+            run("(list 1 \"two\")").unwrap()
+        );
+        // the argument is evaluated once, in the caller's scope
+        run("(define n 0)").unwrap();
+        assert_eq!(run("(time (set! n (+ n 1)))").unwrap(), Expr::Int(1));
+        assert_eq!(run("n").unwrap(), Expr::Int(1));
+        assert!(run("(time)").is_err());
+        // the timing goes where the env's output does
+        let out = SharedBuffer::default();
+        env.set_output(Box::new(out.clone()));
+        eval_str("(time (fact 5))", &mut env).unwrap();
+        assert!(out.text().starts_with("Elapsed time: "), "{}", out.text());
+        assert!(out.text().ends_with('\n'));
+        let mut run = |s: &str| eval_str(s, &mut env);
+        assert!(run("(time (car 1))").is_err());
+    }
+
+    #[test]
+    fn check_try() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        assert_eq!(
+            run(r#"(try (/ 1 0) (catch e "caught"))"#).unwrap(),
+            Expr::String("caught".to_owned())
+        );
+        assert_eq!(run("(try (+ 1 2) (catch e 0))").unwrap(), Expr::Int(3));
+        // the message is bound for the handler, and only for it
+        assert_eq!(
+            run("(try (car 1) (catch e e))").unwrap(),
+            Expr::String("`car` expects a single list".to_owned())
+        );
+        assert_eq!(
+            run("(try (undefined) (catch err (string-concat \"oops: \" err)))").unwrap(),
+            Expr::String("oops: Unbound symbol `undefined`".to_owned())
+        );
+        assert!(run("e").is_err());
+        // the body stops at the first error, and the handler can have several forms
+        run("(define steps 0)").unwrap();
+        assert_eq!(
+            run("(try (set! steps 1) (car 1) (set! steps 2) (catch e (set! steps (+ steps 10)) steps))")
+                .unwrap(),
+            Expr::Int(11)
+        );
+        // errors inside calls are caught too, and the handler's own errors aren't
+        run("(define f (lambda (x) (/ x 0)))").unwrap();
+        assert_eq!(
+            run("(try (f 1) (catch e 'caught))").unwrap(),
+            run("'caught").unwrap()
+        );
+        assert!(run("(try (car 1) (catch e (car 2)))").is_err());
+
+        assert!(run("(try (+ 1 2))").is_err());
+        assert!(run("(try 1 (rescue e 2))").is_err());
+        assert!(run("(try 1 (catch \"e\" 2))").is_err());
+    }
+
+    #[test]
+    fn check_error_builtin() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        let err = run(r#"(error "boom")"#).unwrap_err();
+        assert_eq!(err.to_string(), "boom");
+        assert!(matches!(err.downcast_ref::<Error>(), Some(Error::UserError(m)) if m == "boom"));
+        // it propagates out of calls, and `try` can catch it
+        run(r#"(define check (lambda (x) (if (< x 0) (error "negative") x)))"#).unwrap();
+        assert_eq!(run("(check 1)").unwrap(), Expr::Int(1));
+        assert_eq!(run("(check -1)").unwrap_err().to_string(), "negative");
+        assert_eq!(
+            run(r#"(try (check -1) (catch e (string-concat "caught " e)))"#).unwrap(),
+            Expr::String("caught negative".to_owned())
+        );
+        assert_eq!(
+            run(r#"(try (error "boom") (catch e e))"#).unwrap(),
+            Expr::String("boom".to_owned())
+        );
+
+        assert!(!matches!(
+            run("(error 'boom)").unwrap_err().downcast_ref::<Error>(),
+            Some(Error::UserError(_))
+        ));
+        assert!(run("(error)").is_err());
+    }
+
+    #[test]
+    fn check_delay_force() {
+        let mut env = default_env();
+        let mut run = |s: &str| eval_str(s, &mut env);
+
+        run("(define count 0)").unwrap();
+        run("(define p (delay (begin (set! count (+ count 1)) (* 6 7))))").unwrap();
+        // nothing runs until the first `force`
+        assert_eq!(run("count").unwrap(), Expr::Int(0));
+        assert_eq!(run("(force p)").unwrap(), Expr::Int(42));
+        assert_eq!(run("count").unwrap(), Expr::Int(1));
+        // later forces return the cached value without running it again
+        assert_eq!(run("(force p)").unwrap(), Expr::Int(42));
+        assert_eq!(run("(let ((q p)) (force q))").unwrap(), Expr::Int(42));
+        assert_eq!(run("count").unwrap(), Expr::Int(1));
+
+        assert_eq!(run("(force 5)").unwrap(), Expr::Int(5));
+        assert_eq!(run("(type-of p)").unwrap().to_string(), "promise");
+        assert_eq!(run("(equal? p p)").unwrap(), Expr::Bool(true));
+        assert_eq!(
+            run("(equal? (delay 1) (delay 1))").unwrap(),
+            Expr::Bool(false)
+        );
+        // an error leaves the promise to be forced again
+        run("(define bad (delay (undefined-fn)))").unwrap();
+        assert!(run("(force bad)").is_err());
+        assert!(run("(force bad)").is_err());
+
+        // an infinite stream of integers, worked out only as far as needed
+        run("(define ints-from (lambda (n) (cons-stream n (ints-from (+ n 1)))))").unwrap();
+        assert_eq!(
+            run("(stream-car (stream-cdr (stream-cdr (ints-from 1))))").unwrap(),
+            Expr::Int(3)
+        );
+        assert!(run("(delay)").is_err());
+        assert!(run("(cons-stream 1)").is_err());
+    }
+
+    #[test]
+    fn check_error_kinds() {
+        let mut env = default_env();
+        let mut kind = |s: &str| {
+            let err = eval_program(s, &mut env).unwrap_err();
+            let err = err.downcast::<Error>().expect("a Largo error");
+            (format!("{err:?}"), err.to_string())
+        };
+
+        let (kind_of, text) = kind("undefined");
+        assert_eq!(kind_of, r#"UnboundSymbol("undefined")"#);
+        assert_eq!(text, "Unbound symbol `undefined`");
+        assert!(kind("(set! undefined 1)").0.starts_with("UnboundSymbol"));
+
+        assert_eq!(kind("(1 2)").1, "`1` is not a function");
+        assert_eq!(kind("('x)").1, "`(quote x)` is not a function");
+        assert!(kind("(apply 'x (list))").0.starts_with("NotCallable"));
+
+        assert_eq!(
+            kind("((lambda (a b) a) 1)").0,
+            r#"ArityMismatch { callee: "Lambda", min: 2, max: Some(2), got: 1 }"#
+        );
+        assert_eq!(kind("(sqrt 1 2)").1, "`sqrt` expected 1 argument, got 2");
+        assert_eq!(
+            kind("((lambda (a (b 1) . c) a))").1,
+            "Lambda expected at least 1 argument, got 0"
+        );
+        assert_eq!(
+            kind("((lambda (a (b 1) (c 2)) a))").1,
+            "Lambda expected 1 to 3 arguments, got 0"
+        );
+
+        assert_eq!(
+            kind(r#"(+ 1 "2")"#).0,
+            r#"TypeError { expected: "number", got: "string" }"#
+        );
+        assert_eq!(kind("(- 'x)").1, "Expected number, got symbol");
+
+        assert_eq!(
+            kind("'").1,
+            "Syntax error; line:1 col:1; Expected an expression after `'`"
+        );
+        assert!(kind("").0.starts_with("ParseError"));
+        assert!(kind(")").0.starts_with("ParseError"));
+    }
+
+    #[test]
+    fn check_parse_error_spans() {
+        let span = |input: &str| {
+            let mut env = default_env();
+            let err = eval_str(input, &mut env).unwrap_err();
+            match err.downcast_ref::<Error>() {
+                Some(Error::ParseError { span, .. }) => span.clone(),
+                _ => panic!("expected a parse error, got {err}"),
+            }
+        };
+        let tokens = tokenize("(é \"ab\")".to_owned()).unwrap();
+        let spans: Vec<Range<usize>> = tokens.into_iter().map(|t| t.span).collect();
+        assert_eq!(spans, [0..1, 1..3, 4..8, 8..9]);
+
+        assert_eq!(span("\n  )"), 3..4);
+        assert_eq!(span("(+ 1 2))"), 7..8);
+        // offsets count bytes, so multibyte chars before the error shift it
+        assert_eq!(span("\"é\" )"), 5..6);
+        assert_eq!(span("(list 1 \"open"), 8..13);
+        assert_eq!(span("#| never closed"), 0..15);
+        assert_eq!(span(r"(#\bogus)"), 1..8);
+        assert_eq!(span("(list 1 ')"), 9..10);
+        assert_eq!(span(""), 0..0);
+        assert_eq!(span("'"), 0..1);
+
+        let input = "(+ 1 2))";
+        assert_eq!(&input[span(input)], ")");
+    }
+
+    // a writer tests can read back from after handing it over
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl SharedBuffer {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn check_trace() {
+        let mut env = default_env();
+        let out = SharedBuffer::default();
+        env.set_trace(Some(Box::new(out.clone())));
+        eval_str("(+ 1 (+ 2 3))", &mut env).unwrap();
+        assert_eq!(out.text(), "(+ 1 (+ 2 3))\n  (+ 2 3)\n  => 5\n=> 6\n");
+
+        // the scopes made while evaluating trace to the same place, and the
+        // body of the `let`, a tail position, is reported as part of it
+        let out = SharedBuffer::default();
+        env.set_trace(Some(Box::new(out.clone())));
+        assert!(eval_str("(let ((x 1)) (list x (car x)))", &mut env).is_err());
+        assert_eq!(
+            out.text(),
+            "(let ((x 1)) (list x (car x)))\n  (car x)\n  !! `car` expects a single list\n\
+             !! `car` expects a single list\n"
+        );
+
+        env.set_trace(None);
+        eval_str("(+ 1 (+ 2 3))", &mut env).unwrap();
+        assert!(!out.text().contains("=> 6"));
+
+        assert_eq!(
+            eval_line(":trace off", &mut env).unwrap().as_deref(),
+            Some("Tracing off")
+        );
+        assert!(eval_line(":trace", &mut env).is_err());
+
+        // in a session, tracing goes where the session's output does
+        let out = SharedBuffer::default();
+        let config = ReplConfig {
+            banner: None,
+            init_file: None,
+            ..ReplConfig::default()
+        };
+        let input = ":trace on\n(+ 1 (* 2 3))\n:trace off\n(+ 1 2)\n";
+        run_repl_with(
+            &config,
+            &mut PlainReader(input.as_bytes()),
+            Box::new(out.clone()),
+        )
+        .unwrap();
+        assert_eq!(
+            out.text(),
+            ">>> Tracing on\n>>> (+ 1 (* 2 3))\n  (* 2 3)\n  => 6\n=> 7\n7\n>>> Tracing off\n>>> 3\n>>> "
+        );
+    }
+
+    #[test]
+    fn check_repl_session() {
+        let input =
+            "(define x 2)\n\n(+ x\n   1)\n; note\n(print \"hi\")\n(car 1)\n(define y 1) (+ y\n x)\nquit\n(+ 1 1)\n";
+        let out = SharedBuffer::default();
+        // leave out whatever `~/.largorc` the machine running the tests has
+        let config = ReplConfig {
+            init_file: None,
+            ..ReplConfig::default()
+        };
+        run_repl_with(
+            &config,
+            &mut PlainReader(input.as_bytes()),
+            Box::new(out.clone()),
+        )
+        .unwrap();
+        assert_eq!(
+            out.text(),
+            "~~~~ Largo ~~~~\n>>> 2\n>>> >>> ... 3\n>>> >>> hi()\n>>> Error: `car` expects a single list\n\
+             >>> ... 3\n>>> "
+        );
+
+        // the session also ends when the input runs out
+        let out = SharedBuffer::default();
+        run_repl_with(
+            &config,
+            &mut PlainReader("(+ 1 2)".as_bytes()),
+            Box::new(out.clone()),
+        )
+        .unwrap();
+        assert_eq!(out.text(), "~~~~ Largo ~~~~\n>>> 3\n>>> ");
+    }
+
+    #[test]
+    fn check_repl_load_error() {
+        let path = std::env::temp_dir().join(format!("largo-bad-{}.lg", std::process::id()));
+        fs::write(&path, "(define ok 1)\n(car 1)\n").unwrap();
+        let load = format!("(load \"{}\")", path.display());
+
+        // the report names the file and what went wrong in it
+        let mut env = default_env();
+        let err = eval_line(&load, &mut env).unwrap_err();
+        assert_eq!(
+            error_report(&err),
+            format!(
+                "Error: Error in `{}`: In the form at line:2 col:1: `car` expects a single list",
+                path.display()
+            )
+        );
+        let err = eval_line("(load \"/nonexistent.lg\")", &mut env).unwrap_err();
+        let report = error_report(&err);
+        assert!(
+            report.starts_with("Error: Could not read `/nonexistent.lg`: ") && report.len() > 40,
+            "{report}"
+        );
+
+        // the session reports it to its output, and carries on past it
+        let out = SharedBuffer::default();
+        let config = ReplConfig {
+            banner: None,
+            init_file: None,
+            ..ReplConfig::default()
+        };
+        let input = format!("{load}\n(load \"/nonexistent.lg\")\nok\n");
+        run_repl_with(
+            &config,
+            &mut PlainReader(input.as_bytes()),
+            Box::new(out.clone()),
+        )
+        .unwrap();
+        let text = out.text();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines[0],
+            format!(
+                ">>> Error: Error in `{}`: In the form at line:2 col:1: `car` expects a single list",
+                path.display()
+            )
+        );
+        assert!(lines[1].starts_with(">>> Error: Could not read `/nonexistent.lg`: "));
+        assert_eq!(lines[2..], [">>> 1", ">>> "]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn check_repl_init_file() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("largo-init-{}.largo", std::process::id()));
+        fs::write(
+            &path,
+            "(define greet (lambda (name) (string-concat \"hi \" name)))\n(print \"loaded\")\n",
+        )
+        .unwrap();
+        let mut config = ReplConfig {
+            banner: None,
+            init_file: Some(path.clone()),
+            ..ReplConfig::default()
+        };
+        let out = SharedBuffer::default();
+        let input = "(greet \"you\")\n";
+        run_repl_with(
+            &config,
+            &mut PlainReader(input.as_bytes()),
+            Box::new(out.clone()),
+        )
+        .unwrap();
+        assert_eq!(out.text(), "loaded>>> \"hi you\"\n>>> ");
+
+        // an error part way through keeps what came before and still starts
+        fs::write(&path, "(define a 1)\n(car 1)\n(define b 2)\n").unwrap();
+        let out = SharedBuffer::default();
+        let input = "a\nb\n";
+        run_repl_with(
+            &config,
+            &mut PlainReader(input.as_bytes()),
+            Box::new(out.clone()),
+        )
+        .unwrap();
+        assert_eq!(
+            out.text(),
+            format!(
+                "Error in `{}`: In the form at line:2 col:1: `car` expects a single list\n\
+                 >>> 1\n>>> Error: Unbound symbol `b`\n>>> ",
+                path.display()
+            )
+        );
+        fs::remove_file(&path).unwrap();
+
+        // as does a missing file
+        config.init_file = Some(dir.join("largo-no-such-init-file.largo"));
+        let out = SharedBuffer::default();
+        run_repl_with(
+            &config,
+            &mut PlainReader("1\n".as_bytes()),
+            Box::new(out.clone()),
+        )
+        .unwrap();
+        assert_eq!(out.text(), ">>> 1\n>>> ");
+    }
+
+    #[test]
+    fn check_repl_config() {
+        let input = "(+ 1\n2)\n(print \"hi\")\n";
+        let mut config = ReplConfig {
+            prompt: "lisp> ".to_owned(),
+            continuation_prompt: "   | ".to_owned(),
+            banner: None,
+            echo: true,
+            init_file: None,
+        };
+        let out = SharedBuffer::default();
+        run_repl_with(
+            &config,
+            &mut PlainReader(input.as_bytes()),
+            Box::new(out.clone()),
+        )
+        .unwrap();
+        assert_eq!(out.text(), "lisp>    | 3\nlisp> hi()\nlisp> ");
+
+        // without echo only what the program prints shows up
+        config.echo = false;
+        config.banner = Some("Welcome".to_owned());
+        let out = SharedBuffer::default();
+        run_repl_with(
+            &config,
+            &mut PlainReader(input.as_bytes()),
+            Box::new(out.clone()),
+        )
+        .unwrap();
+        assert_eq!(out.text(), "Welcome\nlisp>    | lisp> hilisp> ");
     }
 }
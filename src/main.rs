@@ -1,7 +1,12 @@
 use anyhow::Result;
 
+use std::env;
+use std::path::PathBuf;
+
 fn main() -> Result<()> {
-    largo::run_repl()?;
-    Ok(())
+    // `largo script.largo` runs the script, bare `largo` starts the REPL
+    match env::args_os().nth(1).map(PathBuf::from) {
+        Some(path) => largo::run_file(&path),
+        None => largo::run_repl(),
+    }
 }
-
@@ -0,0 +1,33 @@
+use largo::{default_env, eval, eval_str, parse, tokenize, Expr};
+
+#[test]
+fn eval_str_shares_env() {
+    let mut env = default_env();
+    eval_str("(define square (lambda (x) (* x x)))", &mut env).unwrap();
+    assert_eq!(
+        eval_str("(square 4)", &mut env).unwrap(),
+        Expr::Int(16)
+    );
+    assert!(eval_str("(square)", &mut env).is_err());
+}
+
+#[test]
+fn custom_pipeline() {
+    let mut env = default_env();
+    let tokens = tokenize("(+ 1 2)".to_owned()).unwrap();
+    let (expr, rest) = parse(&tokens).unwrap();
+    assert!(rest.is_empty());
+    assert_eq!(eval(&expr, &mut env).unwrap(), Expr::Int(3));
+}
+
+#[test]
+fn runaway_recursion_is_an_error() {
+    // on the test harness's own thread, with no extra stack set aside
+    let mut env = default_env();
+    eval_str("(define loop (lambda (n) (+ 1 (loop n))))", &mut env).unwrap();
+    assert_eq!(
+        eval_str("(loop 0)", &mut env).unwrap_err().to_string(),
+        "maximum recursion depth exceeded"
+    );
+    assert!(eval_str(&"(".repeat(100_000), &mut env).is_err());
+}